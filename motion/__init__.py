@@ -6,11 +6,17 @@ from motion.utils import (
     inspect_state,
     get_instances,
     RedisParams,
+    KeyScheme,
+    LockPolicy,
+    DuplicateRunError,
 )
 from motion.instance import ComponentInstance
 from motion.migrate import StateMigrator
+from motion.multi_instance import MultiInstanceAccessor
 from motion.copy_utils import copy_db
 from motion.discard_policy import DiscardPolicy
+from motion.schema import SchemaError
+from motion.json_patch import JsonPatchError
 
 __all__ = [
     "Component",
@@ -19,10 +25,16 @@ __all__ = [
     "clear_instance",
     "inspect_state",
     "StateMigrator",
+    "MultiInstanceAccessor",
     "get_instances",
     "copy_db",
     "RedisParams",
+    "KeyScheme",
+    "LockPolicy",
+    "DuplicateRunError",
     "DiscardPolicy",
+    "SchemaError",
+    "JsonPatchError",
 ]
 
 # Conditionally import Application
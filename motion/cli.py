@@ -3,6 +3,7 @@ import json
 import os
 import sys
 from datetime import datetime
+from typing import Any, Dict, Optional
 
 import click
 import redis
@@ -10,6 +11,50 @@ import yaml
 from rich.console import Console
 
 from motion import clear_instance, get_instances, inspect_state
+from motion.utils import doctor as run_doctor
+
+
+def load_component(filename: str) -> Any:
+    """Imports a component given as `filename:component` from the current
+    directory."""
+    if ":" not in filename:
+        raise ValueError("Component must be in the format `filename:component`.")
+
+    # Remove the file extension if present
+    module_name, component_name = filename.replace(".py", "").split(":")
+    if not module_name or not component_name:
+        raise ValueError("Component must be in the format `filename:component`.")
+
+    module_dir = os.getcwd()
+    sys.path.insert(0, module_dir)
+    module = importlib.import_module(module_name)
+
+    try:
+        return getattr(module, component_name)
+    except AttributeError as e:
+        raise ValueError(str(e))
+
+
+def connection_kwargs(component_path: Optional[str]) -> Dict[str, Any]:
+    """Key scheme and Redis connection config of the component given as
+    `filename:component`, so commands look in the Redis it uses."""
+    if component_path is None:
+        return {}
+
+    component = load_component(component_path)
+    return {
+        "key_scheme": component._key_scheme,
+        "redis_params": component._redis_params,
+    }
+
+
+component_path_option = click.option(
+    "--component-path",
+    type=str,
+    default=None,
+    help="Component as `filename:component`, to use its Redis connection and "
+    + "key scheme. Defaults to the MOTION_REDIS_* environment variables.",
+)
 
 
 @click.group()
@@ -66,35 +111,15 @@ def init() -> None:
 def visualize(filename: str, output: str) -> None:
     """Visualize a component."""
     red_x = "\u274C"  # Unicode code point for red "X" emoji
-    if ":" not in filename:
-        click.echo(
-            f"{red_x} Component must be in the format " + "`filename:component`."
-        )
-        return
-
-    # Remove the file extension if present
-    module = filename.replace(".py", "")
-
-    first, instance = module.split(":")
-    if not first or not instance:
-        click.echo(
-            f"{red_x} Component must be in the format " + "`filename:component`."
-        )
-        return
-
-    module_dir = os.getcwd()
-    sys.path.insert(0, module_dir)
-    module = importlib.import_module(first)  # type: ignore
-
-    # Get the class instance
     try:
-        class_instance = getattr(module, instance)
-    except AttributeError as e:
+        class_instance = load_component(filename)
+    except ValueError as e:
         click.echo(f"{red_x} {e}")
         return
 
     # Get the graph
     graph = class_instance.get_graph()
+    instance = filename.replace(".py", "").split(":")[1]
 
     # Dump the graph to a file with the date
     ts = datetime.now().strftime("%Y-%m-%d-%H-%M-%S")
@@ -110,7 +135,8 @@ def visualize(filename: str, output: str) -> None:
     "clear", epilog="Example usage:\n motion clear MyComponent__myinstance"
 )
 @click.argument("instance", type=str, required=True)
-def clear(instance: str) -> None:
+@component_path_option
+def clear(instance: str, component_path: Optional[str]) -> None:
     """Clears the state and cached results for a component instance.
 
     Args:
@@ -122,7 +148,7 @@ def clear(instance: str) -> None:
     checkmark = "\u2705"  # Unicode code point for checkmark emoji
     with console.status("Clearing instance", spinner="dots"):
         try:
-            found = clear_instance(instance)
+            found = clear_instance(instance, **connection_kwargs(component_path))
         except ValueError as e:
             click.echo(f"{red_x} {e}")
             return
@@ -141,7 +167,8 @@ def clear(instance: str) -> None:
     "inspect", epilog="Example usage:\n motion inspect MyComponent__myinstance"
 )
 @click.argument("instance", type=str, required=True)
-def inspect(instance: str) -> None:
+@component_path_option
+def inspect(instance: str, component_path: Optional[str]) -> None:
     """Prints the saved state for a component instance. Does not apply
     any loadState() transformations.
 
@@ -154,7 +181,7 @@ def inspect(instance: str) -> None:
     checkmark = "\u2705"  # Unicode code point for checkmark emoji
     with console.status("Inspecting instance", spinner="dots"):
         try:
-            state = inspect_state(instance)
+            state = inspect_state(instance, **connection_kwargs(component_path))
         except ValueError as e:
             click.echo(f"{red_x} {e}")
             return
@@ -168,7 +195,8 @@ def inspect(instance: str) -> None:
 
 @motioncli.command("list", epilog="Example usage:\n motion list MyComponent")
 @click.argument("component", type=str, required=True)
-def list(component: str) -> None:
+@component_path_option
+def list(component: str, component_path: Optional[str]) -> None:
     """Lists all instances of a component.
 
     Args:
@@ -179,7 +207,7 @@ def list(component: str) -> None:
     checkmark = "\u2705"  # Unicode code point for checkmark emoji
     with console.status("Getting instances for component", spinner="dots"):
         try:
-            instances = get_instances(component)
+            instances = get_instances(component, **connection_kwargs(component_path))
         except ValueError as e:
             click.echo(f"{red_x} {e}")
             return
@@ -195,5 +223,26 @@ def list(component: str) -> None:
     )
 
 
+@motioncli.command("doctor", epilog="Example usage:\n motion doctor")
+@component_path_option
+def doctor(component_path: Optional[str]) -> None:
+    """Checks the connection to Redis and that Motion can use it, to debug
+    misconfigured MOTION_REDIS_* settings."""
+    console = Console()
+    red_x = "\u274C"
+    checkmark = "\u2705"  # Unicode code point for checkmark emoji
+    question_mark = "\u2753"
+    with console.status("Checking Redis", spinner="dots"):
+        try:
+            report = run_doctor(**connection_kwargs(component_path))
+        except ValueError as e:
+            click.echo(f"{red_x} {e}")
+            return
+
+    for check in report:
+        mark = {True: checkmark, False: red_x, None: question_mark}[check["ok"]]
+        click.echo(f"{mark} {check['check']}: {check['detail']}")
+
+
 if __name__ == "__main__":
     motioncli()
@@ -1,19 +1,33 @@
+import atexit
 import inspect
 import os
-from typing import Any, Callable, Dict, List, Literal, Optional, Union
+from typing import Any, Callable, Dict, List, Literal, Optional, Tuple, Union
+
+import redis
 
 from motion.dicts import Params
 from motion.discard_policy import DiscardPolicy, validate_policy
 from motion.instance import ComponentInstance
 from motion.route import Route
+from motion.schema import validate_schema
 from motion.utils import (
+    DEFAULT_KEY_SCHEME,
     DEFAULT_KEY_TTL,
+    KeyScheme,
+    RedisParams,
     clear_dev_instances,
+    get_redis_params,
     import_config,
+    loadState,
     random_passphrase,
+    to_redis_params,
     validate_args,
 )
 
+# Key schemes and Redis connection configs of the instances created in dev
+# mode, to clean up at exit
+DEV_KEY_SCHEMES: List[Tuple[KeyScheme, Any]] = []
+
 
 class Component:
     """Component class for creating Motion components.
@@ -128,6 +142,16 @@ class Component:
         name: str,
         params: Dict[str, Any] = {},
         cache_ttl: int = DEFAULT_KEY_TTL,
+        max_state_bytes: Optional[int] = None,
+        max_state_keys: Optional[int] = None,
+        schema: Optional[Dict[str, Any]] = None,
+        schema_version: Optional[int] = None,
+        track_access: bool = False,
+        history_size: Optional[int] = None,
+        key_scheme: Optional[KeyScheme] = None,
+        cache_returns: Literal["live", "copy"] = "live",
+        redis_params: Union[RedisParams, Dict[str, Any], redis.Redis, None] = None,
+        on_decode_error: Union[Literal["raise", "skip"], Callable] = "raise",
     ):
         """Creates a new Motion component.
 
@@ -141,15 +165,114 @@ class Component:
             cache_ttl (int, optional):
                 Time to live for cached serve results (seconds).
                 Defaults to 1 day. Set to 0 to disable caching.
+            max_state_bytes (Optional[int], optional):
+                Maximum size (bytes) of an instance's serialized state.
+                Writes that would exceed it raise a ValueError before
+                anything is sent to Redis. Defaults to None (no limit).
+            max_state_keys (Optional[int], optional):
+                Maximum number of keys in an instance's state. Writes that
+                would exceed it raise a ValueError. Defaults to None
+                (no limit).
+            schema (Optional[Dict[str, Any]], optional):
+                Mapping of state keys to their expected types (or tuples
+                of types). Writing a key that is not in the schema, or a
+                value of the wrong type, raises a SchemaError naming the key
+                and the write is rejected. Every key the state holds, including
+                the ones init_state returns, must be declared. Reading a value
+                of the wrong type also raises a SchemaError. Defaults to None.
+            schema_version (Optional[int], optional):
+                Version of the state schema. It is stored in Redis for each
+                instance, and the `schema_mismatch` hook is called when an
+                instance is created whose stored version differs.
+                Defaults to None.
+            track_access (bool, optional):
+                Whether to count reads of each state key, so that hot keys
+                can be found with `access_report`. Counts are kept locally and
+                flushed to Redis periodically. Defaults to False.
+            history_size (Optional[int], optional):
+                Number of past values to keep for each state key, so that
+                they can be read with `get_at_version`. Values must be
+                picklable. Defaults to None (no history).
+            key_scheme (Optional[KeyScheme], optional):
+                Prefixes of the Redis keys the component's instances are
+                stored under. Useful if the default `MOTION_*` keys conflict
+                with another system's keys in a shared Redis. Defaults to
+                None, which uses the default KeyScheme.
+            cache_returns (str, optional):
+                What `read_state` and `items_with_meta` return. "live"
+                returns the objects held in the instance's in-memory copy of
+                the state, so mutating them silently diverges it from Redis.
+                "copy" returns deep copies instead, so such mutations have no
+                effect. Defaults to "live".
+            redis_params (Union[RedisParams, Dict[str, Any], redis.Redis, None],
+                optional):
+                Connection config for the Redis the component's instances
+                are stored in: a RedisParams, a dict of redis.Redis kwargs,
+                or an existing redis.Redis client whose parameters (SSL,
+                socket, and retry options) are copied. Resolved when
+                connecting, so `MOTION_REDIS_*` settings loaded later still
+                fill in what isn't passed. Defaults to None, which reads the
+                `MOTION_REDIS_*` environment variables.
+            on_decode_error (Union[str, Callable], optional):
+                What to do when an instance's stored state can't be
+                deserialized (e.g., it is corrupt). "raise" raises the error,
+                blocking access to the instance. "skip" logs the error and
+                uses an empty, read-only state: writes raise a ValueError
+                until the stored state is repaired with `set_raw_state`. A
+                callable is passed the instance id, the raw bytes, and the
+                exception, and returns the state to use, e.g., to recover
+                data or save the bytes elsewhere; returning None is the same
+                as "skip". Defaults to "raise".
         """
         if cache_ttl is None or cache_ttl < 0:
             raise ValueError(
                 "cache_ttl must be 0 (caching disabled) or a positive integer."
             )
 
+        for limit_name, limit in [
+            ("max_state_bytes", max_state_bytes),
+            ("max_state_keys", max_state_keys),
+            ("history_size", history_size),
+        ]:
+            if limit is not None and limit <= 0:
+                raise ValueError(f"{limit_name} must be None or a positive integer.")
+
+        if schema is not None:
+            validate_schema(schema)
+
+        if key_scheme is not None and not isinstance(key_scheme, KeyScheme):
+            raise TypeError("key_scheme must be a KeyScheme.")
+
+        if on_decode_error not in ["raise", "skip"] and not callable(on_decode_error):
+            raise ValueError("on_decode_error must be 'raise', 'skip', or a callable.")
+
+        if cache_returns not in ["live", "copy"]:
+            raise ValueError("cache_returns must be either 'live' or 'copy'.")
+
+        if schema_version is not None and (
+            not isinstance(schema_version, int) or schema_version < 0
+        ):
+            raise ValueError("schema_version must be None or a non-negative integer.")
+
         self._name = name
         self._params = Params(params)
         self._cache_ttl = cache_ttl
+        self._max_state_bytes = max_state_bytes
+        self._max_state_keys = max_state_keys
+        self._schema = schema
+        self._schema_version = schema_version
+        self._track_access = track_access
+        self._history_size = history_size
+        self._key_scheme = key_scheme or DEFAULT_KEY_SCHEME
+        self._cache_returns = cache_returns
+        # Only checked here; resolved with get_redis_params when connecting
+        to_redis_params(redis_params)
+        self._redis_params = redis_params
+        self._on_decode_error = on_decode_error
+
+        # Clients used by `peek`, one per set of connection params, closed
+        # at exit
+        self._peek_clients: Dict[str, redis.Redis] = {}
 
         # Set up routes
         self._serve_routes: Dict[str, Route] = {}
@@ -157,6 +280,8 @@ class Component:
         self._init_state_func: Optional[Callable] = None
         self._save_state_func: Optional[Callable] = None
         self._load_state_func: Optional[Callable] = None
+        self._schema_mismatch_func: Optional[Callable] = None
+        self._upgrade_state_func: Optional[Callable] = None
 
     @property
     def cache_ttl(self) -> int:
@@ -307,6 +432,77 @@ class Component:
         self._load_state_func = func
         return func
 
+    def schema_mismatch(self, func: Callable) -> Callable:
+        """Decorator for the schema_mismatch hook. This function is called
+        when an instance is created and the schema version stored in Redis
+        for it differs from the component's `schema_version` (e.g., after
+        deploying code that expects a new state schema). The stored version
+        is then set to the component's `schema_version`. If the hook raises
+        an exception, the instance is not created.
+
+        Usage:
+        ```python
+        from motion import Component
+
+        MyComponent = Component(
+            "MyComponent", schema={"count": int}, schema_version=2
+        )
+
+        @MyComponent.schema_mismatch
+        def on_mismatch(instance_id, stored_version, schema_version):
+            # stored_version is None if no version was stored yet
+            logging.warning(
+                f"{instance_id} has schema v{stored_version}, "
+                + f"expected v{schema_version}"
+            )
+        ```
+
+        Args:
+            func (Callable): Function that takes the instance id, the stored
+                schema version, and the component's schema version.
+
+        Returns:
+            Callable: Decorated schema_mismatch function.
+        """
+        self._schema_mismatch_func = func
+        return func
+
+    def upgrade_state(self, func: Callable) -> Callable:
+        """Decorator for the upgrade_state hook. When an instance is created
+        and the schema version stored in Redis for it differs from the
+        component's `schema_version`, this function is called with the
+        stored state and version, and the state it returns replaces the
+        stored state. The upgrade runs while holding the instance lock, so
+        only one process upgrades each instance. If the hook raises an
+        exception, the state is left as is and the instance is not created.
+
+        Usage:
+        ```python
+        from motion import Component
+
+        MyComponent = Component(
+            "MyComponent", schema={"counts": dict}, schema_version=3
+        )
+
+        @MyComponent.upgrade_state
+        def upgrade(state, stored_version):
+            # stored_version is None if no version was stored yet
+            if stored_version is None or stored_version < 3:
+                state["counts"] = {"total": state.pop("count", 0)}
+            return state
+        ```
+
+        Args:
+            func (Callable): Function that takes the stored state and the
+                stored schema version, and returns the upgraded state as a
+                dict.
+
+        Returns:
+            Callable: Decorated upgrade_state function.
+        """
+        self._upgrade_state_func = func
+        return func
+
     def serve(self, keys: Union[str, List[str]]) -> Callable:
         """Decorator for any serve operation for a flow through the
         component. Takes in a string or list of strings that represents the
@@ -488,6 +684,188 @@ class Component:
 
         return decorator
 
+    def peek(
+        self, instance_id: str, key: str, default_value: Optional[Any] = None
+    ) -> Any:
+        """Reads a key from the state of an instance of this component,
+        without creating a component instance (so no update task is
+        started and init_state is not run). Useful for reading another
+        component's shared state, like a global config, from a serve op.
+        The Redis client is reused across calls and closed at exit.
+
+        Usage:
+        ```python
+        from motion import Component
+
+        Config = Component("Config")
+        MyComponent = Component("MyComponent")
+
+        @MyComponent.serve("predict")
+        def predict(state, props):
+            threshold = Config.peek("global", "threshold", 0.5)
+            return state["model"].predict(props["x"]) > threshold
+        ```
+
+        Args:
+            instance_id (str): Id of the instance to read from.
+            key (str): Key in the state to get the value for.
+            default_value (Optional[Any], optional): Default value to return
+                if the key is not found. Defaults to None.
+
+        Raises:
+            ValueError: If the instance does not exist.
+
+        Returns:
+            Any: Current value for the key, or default_value if the key
+            is not found.
+        """
+        instance_name = f"{self.name}__{instance_id}"
+        redis_con = self._peek_client()
+
+        version_key = self._key_scheme.version
+        exists = redis_con.exists(f"{version_key}:{instance_name}") or (
+            os.getenv("MOTION_ENV", "prod") == "dev"
+            and redis_con.exists(f"{version_key}:DEV:{instance_name}")
+        )
+        if not exists:
+            raise ValueError(f"Instance {instance_name} does not exist.")
+
+        state, _ = loadState(
+            redis_con,
+            instance_name,
+            self._load_state_func,
+            schema=self._schema,
+            key_scheme=self._key_scheme,
+            on_decode_error=self._on_decode_error,
+        )
+
+        return state.get(key, default_value)  # type: ignore
+
+    def _peek_client(self) -> redis.Redis:
+        # Reuse a client per set of connection params, since `peek` is
+        # meant to be called on serving paths
+        params = get_redis_params(self._redis_params).dict()
+        cache_key = repr(sorted(params.items()))
+        redis_con = self._peek_clients.get(cache_key)
+        if redis_con is None:
+            if not self._peek_clients:
+                atexit.register(self._close_peek_clients)
+            redis_con = self._peek_clients.setdefault(
+                cache_key, redis.Redis(**params)
+            )
+        return redis_con
+
+    def _close_peek_clients(self) -> None:
+        for redis_con in self._peek_clients.values():
+            redis_con.close()
+        self._peek_clients.clear()
+
+    def __getstate__(self) -> Dict[str, Any]:
+        # Clients can't be pickled, e.g., when routes are sent to an update
+        # process; they are created again on the next `peek`
+        attrs = self.__dict__.copy()
+        attrs["_peek_clients"] = {}
+        return attrs
+
+    def broadcast_set(
+        self, key: str, value: Any, chunk_size: int = 10, writer_id: str = ""
+    ) -> int:
+        """Sets a key to the same value in the state of every instance of
+        this component, e.g., to push a global threshold or model version
+        pin. Instances are updated in chunks; each chunk holds the locks
+        of its instances and writes their states in one pipeline, bumping
+        each instance's version. Up to 4 chunks are written in parallel, so
+        up to 4 * chunk_size instance locks are held at once, blocking
+        those instances' update ops.
+
+        Usage:
+        ```python
+        from motion import Component
+
+        MyComponent = Component("MyComponent")
+
+        if __name__ == "__main__":
+            MyComponent.broadcast_set("threshold", 0.8) # Returns the
+            # number of instances updated
+        ```
+
+        Args:
+            key (str): Key in the state to set.
+            value (Any): Value to set the key to.
+            chunk_size (int, optional): Number of instances to update per
+                pipeline. Defaults to 10.
+            writer_id (str, optional): Identifier recorded alongside the
+                writes, retrievable with `last_writer`. Defaults to ""
+                which uses "<hostname>:<pid>".
+
+        Raises:
+            SchemaError: If the component has a schema and the key or value
+                does not match it. No instances are updated.
+            ValueError: If an instance's state would exceed the component's
+                limits. Chunks that were already written stay written.
+
+        Returns:
+            int: Number of instances updated.
+        """
+        from motion.multi_instance import MultiInstanceAccessor
+
+        return MultiInstanceAccessor(
+            self, write_chunk_size=chunk_size, writer_id=writer_id
+        ).set(key, value)
+
+    def get_many_instances(
+        self,
+        key: str,
+        instance_ids: List[str] = [],
+        default_value: Optional[Any] = None,
+        chunk_size: int = 100,
+        num_workers: int = 4,
+    ) -> Dict[str, Any]:
+        """Reads a key from the state of many instances of this component
+        at once, e.g., for a monitoring job that reads the same "stats" key
+        from every instance. States are fetched in chunks, with one
+        pipeline per chunk, and chunks are fetched and deserialized in
+        parallel threads. No instance handles are created and no locks are
+        taken.
+
+        Usage:
+        ```python
+        from motion import Component
+
+        MyComponent = Component("MyComponent")
+
+        if __name__ == "__main__":
+            MyComponent.get_many_instances("stats")
+            # Returns {"instance_id": stats, ...} for all instances
+            MyComponent.get_many_instances("stats", ["a", "b"])
+            # Returns {"a": stats, "b": stats}
+        ```
+
+        Args:
+            key (str): Key in the state to read.
+            instance_ids (List[str], optional): Ids of the instances to
+                read from. Defaults to empty list, which reads from all
+                instances of the component.
+            default_value (Optional[Any], optional): Value for instances
+                where the key is not found. Defaults to None.
+            chunk_size (int, optional): Number of instances to read per
+                pipeline. Defaults to 100.
+            num_workers (int, optional): Number of chunks to read in
+                parallel. Defaults to 4.
+
+        Returns:
+            Dict[str, Any]: Mapping from instance id to value. Instances
+            that don't exist are left out.
+        """
+        from motion.multi_instance import MultiInstanceAccessor
+
+        return MultiInstanceAccessor(
+            self,
+            instance_ids=instance_ids,
+            chunk_size=chunk_size,
+            num_workers=num_workers,
+        ).get(key, default_value)
+
     def __call__(
         self,
         instance_id: str = "",
@@ -498,6 +876,8 @@ class Component:
         redis_socket_timeout: int = 60,
         config_path: str = ".motionrc.yml",
         flush_on_exit: bool = False,
+        writer_id: str = "",
+        write_coalesce_ms: Optional[int] = None,
     ) -> ComponentInstance:
         """Creates and returns a new instance of a Motion component.
         See `ComponentInstance` docs for more info.
@@ -567,12 +947,27 @@ class Component:
                 where you want to make sure all updates are finished after the
                 result is returned, before the function exits. Defaults to
                 False.
+            writer_id (str, optional):
+                Identifier recorded alongside every write this instance makes
+                to the state, retrievable with `last_writer`. Defaults to ""
+                which uses "<hostname>:<pid>".
+            write_coalesce_ms (Optional[int], optional):
+                If set, `write_state` calls are buffered for this many
+                milliseconds and written together, keeping only the latest
+                value per key. Useful for tight loops that repeatedly write
+                the same key (e.g., progress). Buffered writes are visible
+                to this instance right away and can be forced with
+                `flush_writes`. `write_state` then returns None instead of
+                the new version. Defaults to None, which writes immediately.
         Returns:
             ComponentInstance: Component instance to run flows with.
         """
         if not instance_id:
             instance_id = random_passphrase()
 
+        if write_coalesce_ms is not None and write_coalesce_ms <= 0:
+            raise ValueError("write_coalesce_ms must be positive.")
+
         if "__" in instance_id:
             raise ValueError(
                 f"Instance name {instance_id} cannot contain '__'. Strip the component"
@@ -585,9 +980,10 @@ class Component:
         # Set up an atexit hook to clear all instances in dev mode
 
         if os.getenv("MOTION_ENV", "prod") == "dev":
-            if not os.getenv("CLEANUP_DEV_REGISTERED"):
-                import atexit
+            if (self._key_scheme, self._redis_params) not in DEV_KEY_SCHEMES:
+                DEV_KEY_SCHEMES.append((self._key_scheme, self._redis_params))
 
+            if not os.getenv("CLEANUP_DEV_REGISTERED"):
                 from rich.console import Console
 
                 def cleanup_dev() -> None:
@@ -596,7 +992,10 @@ class Component:
                     with console.status(
                         "[bold green]Performing cleanup...[/bold green]", spinner="dots"
                     ):
-                        num_deleted = clear_dev_instances()
+                        num_deleted = sum(
+                            clear_dev_instances(key_scheme, redis_params)
+                            for key_scheme, redis_params in DEV_KEY_SCHEMES
+                        )
 
                     plural = "s" if num_deleted != 1 else ""
                     console.print(
@@ -624,8 +1023,22 @@ class Component:
                 update_task_type=update_task_type,
                 disable_update_task=disable_update_task,
                 cache_ttl=self._cache_ttl,
+                max_state_bytes=self._max_state_bytes,
+                max_state_keys=self._max_state_keys,
+                schema=self._schema,
+                schema_version=self._schema_version,
+                schema_mismatch_func=self._schema_mismatch_func,
+                upgrade_state_func=self._upgrade_state_func,
+                track_access=self._track_access,
+                history_size=self._history_size,
+                key_scheme=self._key_scheme,
+                cache_returns=self._cache_returns,
+                redis_params=get_redis_params(self._redis_params),
+                on_decode_error=self._on_decode_error,
                 redis_socket_timeout=redis_socket_timeout,
                 flush_on_exit=flush_on_exit,
+                writer_id=writer_id,
+                write_coalesce_ms=write_coalesce_ms,
             )
         except RuntimeError:
             raise RuntimeError(
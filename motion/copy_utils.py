@@ -7,18 +7,22 @@ import logging
 
 import redis.asyncio as redis
 
-from motion.utils import RedisParams
+from motion.utils import DEFAULT_KEY_SCHEME, KeyScheme, RedisParams
 
 logger = logging.getLogger(__name__)
 
 
-async def copy_db(src: RedisParams, dest: RedisParams) -> None:
+async def copy_db(
+    src: RedisParams, dest: RedisParams, key_scheme: KeyScheme = DEFAULT_KEY_SCHEME
+) -> None:
     """
     Copy a component and its state from one Redis instance to another.
 
     Args:
         src: RedisParams for the source Redis instance.
         dest: RedisParams for the destination Redis instance.
+        key_scheme: KeyScheme the components were created with. Defaults to
+            the default KeyScheme.
     """
 
     # Verify that src and dest are different
@@ -37,10 +41,17 @@ async def copy_db(src: RedisParams, dest: RedisParams) -> None:
     if await dest_con.ping() is False:
         raise ValueError("Could not connect to destination Redis instance.")
 
-    # Copy all keys prefixed MOTION_STATE: and MOTION_VERSION:
+    # Copy all state, version, schema version, and key metadata (which are
+    # hashes) keys
     try:
-        key_prefixes = ["MOTION_STATE:", "MOTION_VERSION:"]
+        key_prefixes = [
+            f"{key_scheme.state}:",
+            f"{key_scheme.version}:",
+            f"{key_scheme.schema_version}:",
+            f"{key_scheme.key_meta}:",
+        ]
         for key_prefix in key_prefixes:
+            is_hash = key_prefix == f"{key_scheme.key_meta}:"
             logger.info(f"Copying keys with prefix {key_prefix}")
 
             cursor = 0
@@ -54,13 +65,19 @@ async def copy_db(src: RedisParams, dest: RedisParams) -> None:
                 # Pipeline to fetch all values in a single round trip
                 pipeline = src_con.pipeline()
                 for key in keys:
-                    pipeline.get(key)
+                    if is_hash:
+                        pipeline.hgetall(key)
+                    else:
+                        pipeline.get(key)
                 values = await pipeline.execute()
 
                 # Pipeline to set all values in the destination Redis
                 pipeline = dest_con.pipeline()
                 for key, value in zip(keys, values):
-                    pipeline.set(key, value)
+                    if is_hash:
+                        pipeline.hset(key, mapping=value)
+                    else:
+                        pipeline.set(key, value)
                 await pipeline.execute()
 
                 logger.info(f"Copied {len(keys)} keys with prefix {key_prefix}")
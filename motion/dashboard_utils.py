@@ -274,7 +274,14 @@ def writeState(instance_name: str, new_updates: Dict[str, Any]) -> None:
     state.update(new_updates)
 
     # Save the state
-    saveState(state, version, redis_con, instance_name, None)
+    saveState(
+        state,
+        version,
+        redis_con,
+        instance_name,
+        None,
+        updated_keys=new_updates.keys(),
+    )
 
     # Close the connection to the Redis server
     redis_con.close()
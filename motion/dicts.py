@@ -3,7 +3,10 @@ This file contains the props class, which is used to store
 properties of a flow.
 """
 
-from typing import Any, Optional
+from collections import Counter
+from typing import Any, Dict, Optional
+
+from motion.schema import SchemaType, check_value
 
 
 class CustomDict(dict):
@@ -124,15 +127,25 @@ class State(dict):
     ```
     """
 
+    # Class-level default for unpickled states; the component's schema is
+    # attached on load, so it is never pickled with the state
+    _schema: Optional[Dict[str, SchemaType]] = None
+    # Set by the executor when access tracking is on; never pickled
+    _access_counts: Optional[Counter] = None
+    # Set when the stored state couldn't be decoded and was skipped
+    _read_only: bool = False
+
     def __init__(
         self,
         component_name: str,
         instance_id: str,
         *args: Any,
+        schema: Optional[Dict[str, SchemaType]] = None,
         **kwargs: Any,
     ) -> None:
         self.component_name = component_name
         self._instance_id = instance_id
+        self._schema = schema
         super().__init__(*args, **kwargs)
 
     @property
@@ -145,14 +158,81 @@ class State(dict):
         return self._instance_id
 
     def __getitem__(self, key: str) -> object:
+        value = self._lookup(key)
+        self._count_access(key)
+        return value
+
+    def __getstate__(self) -> Dict[str, Any]:
+        attrs = self.__dict__.copy()
+        attrs.pop("_access_counts", None)
+        attrs.pop("_schema", None)
+        attrs.pop("_read_only", None)
+        return attrs
+
+    def get(self, key: str, default: Any = None) -> Any:
+        if key not in self:
+            return default
+        return self[key]
+
+    def _lookup(self, key: str) -> Any:
         try:
-            return super().__getitem__(key)
+            value = super().__getitem__(key)
         except KeyError:
             raise KeyError(
                 f"Key `{key}` not found in state for "
                 + f"instance {self.component_name}__{self._instance_id}."
             )
 
+        if self._schema is not None and key in self._schema:
+            self._check_type(key, value, self._schema[key])
+
+        return value
+
+    def _count_access(self, key: str) -> None:
+        # Only reads made by flows and `read_state` are counted; reads made
+        # internally by the executor use dict.__getitem__ so they aren't
+        if self._access_counts is not None:
+            self._access_counts[key] += 1
+
+    def _check_type(self, key: str, value: Any, expected: SchemaType) -> None:
+        check_value(
+            f"{self.component_name}__{self._instance_id}", key, value, expected
+        )
+
+    def _get_typed(self, key: str, expected: SchemaType) -> Any:
+        value = self._lookup(key)
+        self._check_type(key, value, expected)
+        self._count_access(key)
+        return value
+
+    def get_int(self, key: str) -> int:
+        """Gets the value for the key, raising a SchemaError if it
+        is not an int.
+
+        Usage:
+        ```python
+        @C.serve("count")
+        def count(state, props):
+            return state.get_int("count")
+        ```
+        """
+        return self._get_typed(key, int)  # type: ignore
+
+    def get_float(self, key: str) -> float:
+        """Gets the value for the key as a float, raising a SchemaError
+        if it is not an int or float."""
+        return float(self._get_typed(key, (int, float)))
+
+    def get_str(self, key: str) -> str:
+        """Gets the value for the key, raising a SchemaError if it
+        is not a str."""
+        return self._get_typed(key, str)  # type: ignore
+
+    def get_dict(self, key: str) -> Dict[Any, Any]:
+        """Gets the value for the key, raising a SchemaError if it
+        is not a dict."""
+        return self._get_typed(key, dict)  # type: ignore
+
 
 class Params(dict):
     def __init__(
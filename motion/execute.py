@@ -1,11 +1,17 @@
 import asyncio
+import contextlib
+import fnmatch
 import inspect
+import itertools
+import json
 import logging
 import multiprocessing
 import os
+import socket
 import threading
 import time
 import types
+from collections import Counter
 from concurrent.futures import ThreadPoolExecutor
 from typing import (
     Any,
@@ -13,10 +19,14 @@ from typing import (
     Callable,
     Dict,
     Generator,
+    Iterable,
+    Iterator,
     List,
     Literal,
     Optional,
+    Set,
     Tuple,
+    Union,
 )
 from uuid import uuid4
 
@@ -27,27 +37,87 @@ import requests
 
 from motion.dicts import Properties, State
 from motion.discard_policy import DiscardPolicy
+from motion.json_patch import apply_json_patch
 from motion.route import Route
-from motion.server.update_task import UpdateProcess, UpdateThread
+from motion.schema import SchemaError, validate_state
+from motion.server.update_task import UpdateProcess, UpdateThread, requeue_inflight
 from motion.utils import (
+    DEFAULT_KEY_TTL,
+    DEFAULT_LOCK_POLICY,
+    DuplicateRunError,
     FlowOpStatus,
+    KeyScheme,
+    LockPolicy,
     RedisParams,
     UpdateEvent,
     UpdateEventGroup,
+    deserializeState,
     get_redis_params,
     hash_object,
+    loadHistoryKeys,
+    loadKeyHistory,
+    loadKeyMeta,
+    loadRawState,
     loadState,
     saveState,
 )
 
 logger = logging.getLogger(__name__)
 
+# How often (seconds) locally counted state reads are flushed to redis
+ACCESS_FLUSH_INTERVAL = 10
+
+# How often (seconds) each handle checks for scheduled writes that are due
+SCHEDULED_WRITE_INTERVAL = 1
+
+# Hashes the first of the given state keys that exists, so that the state
+# itself never has to leave redis
+STATE_DIGEST_SCRIPT = """
+for _, key in ipairs(KEYS) do
+    local state = redis.call("GET", key)
+    if state then
+        return redis.sha1hex(state)
+    end
+end
+return false
+"""
+
+# Claims the idempotency key in KEYS[1] and queues the update ops in
+# KEYS[2..] in one step, so a failed enqueue never leaves the key claimed
+ENQUEUE_ONCE_SCRIPT = """
+if not redis.call("SET", KEYS[1], 1, "NX", "EX", ARGV[1]) then
+    return 0
+end
+for i = 2, #KEYS do
+    redis.call("RPUSH", KEYS[i], ARGV[i])
+end
+return 1
+"""
+
+# Removes a scheduled write only if it is still scheduled for the same time
+REMOVE_SCHEDULED_WRITE_SCRIPT = """
+local at = redis.call("ZSCORE", KEYS[1], ARGV[1])
+if at and tonumber(at) == tonumber(ARGV[2]) then
+    return redis.call("ZREM", KEYS[1], ARGV[1])
+end
+return 0
+"""
+
 
 class Executor:
     def __init__(
         self,
         instance_name: str,
         cache_ttl: int,
+        max_state_bytes: Optional[int],
+        max_state_keys: Optional[int],
+        schema: Optional[Dict[str, Any]],
+        schema_version: Optional[int],
+        schema_mismatch_func: Optional[Callable],
+        upgrade_state_func: Optional[Callable],
+        track_access: bool,
+        history_size: Optional[int],
+        key_scheme: KeyScheme,
         init_state_func: Optional[Callable],
         init_state_params: Dict[str, Any],
         save_state_func: Optional[Callable],
@@ -56,12 +126,35 @@ class Executor:
         update_routes: Dict[str, List[Route]],
         update_task_type: Literal["thread", "process"] = "thread",
         disable_update_task: bool = False,
+        redis_params: Optional[RedisParams] = None,
+        on_decode_error: Union[str, Callable] = "raise",
         redis_socket_timeout: int = 60,
+        writer_id: str = "",
+        write_coalesce_ms: Optional[int] = None,
     ):
         self._instance_name = instance_name
         self._component_name = instance_name.split("__")[0]
         self._instance_id = instance_name.split("__")[1]
         self._cache_ttl = cache_ttl
+        self._max_state_bytes = max_state_bytes
+        self._max_state_keys = max_state_keys
+        self._schema = schema
+        self._schema_version = schema_version
+        self._schema_mismatch_func = schema_mismatch_func
+        self._upgrade_state_func = upgrade_state_func
+        self._on_decode_error = on_decode_error
+        self._track_access = track_access
+        self._history_size = history_size
+        self._key_scheme = key_scheme
+        self._access_counts: Counter = Counter()
+        self._access_flushed_at = time.time()
+        self._last_op_info: Optional[Dict[str, Any]] = None
+        self._op_metrics: Counter = Counter()
+        self._writer_id = writer_id or f"{socket.gethostname()}:{os.getpid()}"
+        self._write_coalesce_ms = write_coalesce_ms
+        self._pending_writes: Dict[str, Any] = {}
+        self._pending_lock = threading.Lock()
+        self._flush_timer: Optional[threading.Timer] = None
         self._num_messages = 100
 
         # VictoriaMetrics Configuration
@@ -72,28 +165,52 @@ class Executor:
         self._load_state_func = load_state_func
         self._save_state_func = save_state_func
         self.__lock_prefix = (
-            f"MOTION_LOCK:DEV:{self._instance_name}"
+            f"{self._key_scheme.lock}:DEV:{self._instance_name}"
             if os.getenv("MOTION_ENV", "prod") == "dev"
-            else f"MOTION_LOCK:{self._instance_name}"
+            else f"{self._key_scheme.lock}:{self._instance_name}"
         )
         self.__queue_prefix = (
-            f"MOTION_QUEUE:DEV:{self._instance_name}"
+            f"{self._key_scheme.queue}:DEV:{self._instance_name}"
             if os.getenv("MOTION_ENV", "prod") == "dev"
-            else f"MOTION_QUEUE:{self._instance_name}"
+            else f"{self._key_scheme.queue}:{self._instance_name}"
         )
+        self.__scheduled_key = f"{self.__queue_prefix}/scheduled"
         self.__channel_prefix = (
-            f"MOTION_CHANNEL:DEV:{self._instance_name}"
+            f"{self._key_scheme.channel}:DEV:{self._instance_name}"
             if os.getenv("MOTION_ENV", "prod") == "dev"
-            else f"MOTION_CHANNEL:{self._instance_name}"
+            else f"{self._key_scheme.channel}:{self._instance_name}"
         )
         self.__cache_result_prefix = (
-            f"MOTION_RESULT:DEV:{self._instance_name}"
+            f"{self._key_scheme.result}:DEV:{self._instance_name}"
+            if os.getenv("MOTION_ENV", "prod") == "dev"
+            else f"{self._key_scheme.result}:{self._instance_name}"
+        )
+        self.__schema_version_key = (
+            f"{self._key_scheme.schema_version}:DEV:{self._instance_name}"
+            if os.getenv("MOTION_ENV", "prod") == "dev"
+            else f"{self._key_scheme.schema_version}:{self._instance_name}"
+        )
+        self.__events_channel = (
+            f"{self._key_scheme.events}:DEV:{self._instance_name}"
             if os.getenv("MOTION_ENV", "prod") == "dev"
-            else f"MOTION_RESULT:{self._instance_name}"
+            else f"{self._key_scheme.events}:{self._instance_name}"
+        )
+        self._subscriptions: List[Tuple[str, Callable]] = []
+        self._events_thread: Optional[threading.Thread] = None
+        self._events_stop = threading.Event()
+        self.__access_key = (
+            f"{self._key_scheme.access}:DEV:{self._instance_name}"
+            if os.getenv("MOTION_ENV", "prod") == "dev"
+            else f"{self._key_scheme.access}:{self._instance_name}"
         )
 
         self.running: Any = multiprocessing.Value("b", False)
         self._redis_socket_timeout = redis_socket_timeout
+        self._configured_redis_params = redis_params
+
+        # Calls with a per-call timeout use their own clients, per thread
+        self._local = threading.local()
+        self._timeout_cons: Dict[float, redis.Redis] = {}
 
         self._redis_params, self._redis_con = self._connectToRedis()
         try:
@@ -110,6 +227,8 @@ class Executor:
         # If version does not exist, load state
         self.version: Optional[int] = None
         self._loadState(only_create=True)
+        if self._schema_version is not None:
+            self._checkSchemaVersion()
 
         # Set up routes
         self._serve_routes: Dict[str, Route] = serve_routes
@@ -120,6 +239,9 @@ class Executor:
 
         # Set up update queues, batch sizes, and threads
         self.disable_update_task = disable_update_task
+        self.stop_event = threading.Event()
+        self.monitor_thread: Optional[threading.Thread] = None
+        self.scheduler_thread: Optional[threading.Thread] = None
         if not disable_update_task:
             self.update_task_type = update_task_type
             self._build_fit_jobs()
@@ -128,7 +250,7 @@ class Executor:
 
         # Add component name to set of components if we are not in dev mode
         if os.getenv("MOTION_ENV", "prod") != "dev":
-            self._redis_con.sadd("MOTION_COMPONENTS", self._component_name)
+            self._redis_con.sadd(self._key_scheme.components, self._component_name)
 
     def _setRedis(self, cache_result_key: str, props: Any) -> None:
         """Method to set value in Redis."""
@@ -171,12 +293,17 @@ class Executor:
             except requests.RequestException as e:
                 logger.error(f"Failed to send metric to VictoriaMetrics: {e}")
 
-    def _connectToRedis(self) -> Tuple[RedisParams, redis.Redis]:
-        rp = get_redis_params()
+    def _connectToRedis(
+        self, socket_timeout: Optional[float] = None
+    ) -> Tuple[RedisParams, redis.Redis]:
+        rp = self._configured_redis_params or get_redis_params()
 
         # Put a timeout on the connection
         param_dict = rp.dict()
-        if "socket_timeout" not in param_dict:
+        if socket_timeout is not None:
+            param_dict["socket_timeout"] = socket_timeout
+            param_dict["socket_connect_timeout"] = socket_timeout
+        elif "socket_timeout" not in param_dict:
             param_dict["socket_timeout"] = self._redis_socket_timeout
 
         # Pop all None values
@@ -189,10 +316,14 @@ class Executor:
         # If in dev mode, try loading dev
         redis_v = None
         if os.getenv("MOTION_ENV", "prod") == "dev":
-            redis_v = self._redis_con.get(f"MOTION_VERSION:DEV:{self._instance_name}")
+            redis_v = self._redis_con.get(
+                f"{self._key_scheme.version}:DEV:{self._instance_name}"
+            )
 
         if not redis_v:
-            redis_v = self._redis_con.get(f"MOTION_VERSION:{self._instance_name}")
+            redis_v = self._redis_con.get(
+                f"{self._key_scheme.version}:{self._instance_name}"
+            )
 
         return int(redis_v) if redis_v else None
 
@@ -212,47 +343,479 @@ class Executor:
                         self._instance_name.split("__")[0],
                         self._instance_name.split("__")[1],
                         {},
+                        schema=self._schema,
                     )
                     state.update(self.setUp(**self._init_state_params))
+                    op_info: Dict[str, Any] = {}
                     version = saveState(
                         state,
                         0,
                         self._redis_con,
                         self._instance_name,
                         self._save_state_func,
+                        max_state_bytes=self._max_state_bytes,
+                        max_state_keys=self._max_state_keys,
+                        schema=self._schema,
+                        updated_keys=state.keys(),
+                        writer_id=self._writer_id,
+                        history_size=self._history_size,
+                        key_scheme=self._key_scheme,
+                        op_info=op_info,
                     )
+                    self._recordOp(op_info)
                     assert version == 1, "Version should be 1 after saving state."
+                    if self._schema_version is not None:
+                        self._redis_con.set(
+                            self.__schema_version_key, self._schema_version
+                        )
                     loaded_state = True
 
             if loaded_state:
-                self._state = state
+                self._setState(state)
                 self.version = version
                 return
 
         if not only_create:
             if self.version is None or (self.version and self.version < redis_v):  # type: ignore # noqa: E501
                 # Reload state
+                load_info: Dict[str, Any] = {}
                 new_state, self.version = loadState(
-                    self._redis_con, self._instance_name, self._load_state_func
+                    self._redis_con,
+                    self._instance_name,
+                    self._load_state_func,
+                    schema=self._schema,
+                    key_scheme=self._key_scheme,
+                    op_info=load_info,
+                    on_decode_error=self._on_decode_error,
                 )
+                self._recordOp(load_info)
                 if new_state is None:
                     raise ValueError(
                         f"Error loading state for {self._instance_name}."
                         + " State is None."
                     )
-                self._state = new_state
+                self._setState(new_state)
+
+        self._flushAccessCounts()
+
+    def _setState(self, state: State) -> None:
+        if self._track_access:
+            state._access_counts = self._access_counts
+        # Keep buffered writes visible on top of the reloaded state
+        with self._pending_lock:
+            dict.update(state, self._pending_writes)
+        self._state = state
+
+    @property
+    def _redis_con(self) -> redis.Redis:
+        return getattr(self._local, "redis_con", None) or self.__redis_con
+
+    @_redis_con.setter
+    def _redis_con(self, redis_con: redis.Redis) -> None:
+        self.__redis_con = redis_con
+
+    @contextlib.contextmanager
+    def _timeout(self, timeout: Optional[float]) -> Iterator[None]:
+        # Runs the calls in the block on a client whose commands time out
+        # after the given number of seconds
+        if timeout is None:
+            yield
+            return
+
+        if timeout <= 0:
+            raise ValueError("timeout must be positive.")
+
+        if timeout not in self._timeout_cons:
+            self._timeout_cons[timeout] = self._connectToRedis(timeout)[1]
+        self._local.redis_con = self._timeout_cons[timeout]
+        try:
+            yield
+        finally:
+            self._local.redis_con = None
+
+    @contextlib.contextmanager
+    def _lock(
+        self, lock_policy: Optional[LockPolicy] = None, lock_name: Optional[str] = None
+    ) -> Iterator[None]:
+        # Takes this instance's lock unless another lock name is given
+        lock = self._redis_con.lock(lock_name or self.__lock_prefix, timeout=120)
+        (lock_policy or DEFAULT_LOCK_POLICY).acquire(lock)
+        try:
+            yield
+        finally:
+            lock.release()
+
+    def _recordOp(self, op_info: Dict[str, Any]) -> None:
+        # Nothing is recorded if the state wasn't (de)serialized
+        if not op_info:
+            return
+
+        self._last_op_info = op_info
+        op = op_info["op"]
+        self._op_metrics[f"{op}_count"] += 1
+        self._op_metrics[f"{op}_bytes"] += op_info["bytes"]
+        self._op_metrics[f"{op}_seconds"] += op_info["duration"]
+
+    def _metrics(self) -> Dict[str, Any]:
+        return {
+            f"{op}_{field}": self._op_metrics[f"{op}_{field}"]
+            for op in ["load", "save"]
+            for field in ["count", "bytes", "seconds"]
+        }
+
+    def _flushAccessCounts(self, force: bool = False) -> None:
+        if not self._access_counts:
+            return
+
+        if (
+            not force
+            and time.time() - self._access_flushed_at < ACCESS_FLUSH_INTERVAL
+        ):
+            return
+
+        pipeline = self._redis_con.pipeline()
+        for key, count in self._access_counts.items():
+            pipeline.hincrby(self.__access_key, key, count)
+        pipeline.execute()
+
+        self._access_counts.clear()
+        self._access_flushed_at = time.time()
+
+    def _transferKeys(
+        self,
+        dest_instance_id: str,
+        keys: List[str],
+        lock_policy: Optional[LockPolicy] = None,
+    ) -> None:
+        dest_instance_name = f"{self._component_name}__{dest_instance_id}"
+        if dest_instance_name == self._instance_name:
+            raise ValueError("Cannot transfer keys to the same instance.")
+
+        dest_lock = (
+            f"{self._key_scheme.lock}:DEV:{dest_instance_name}"
+            if os.getenv("MOTION_ENV", "prod") == "dev"
+            else f"{self._key_scheme.lock}:{dest_instance_name}"
+        )
+
+        # Take both locks in a deterministic order so that two transfers
+        # in opposite directions can't deadlock
+        first_lock, second_lock = sorted([self.__lock_prefix, dest_lock])
+        with self._lock(lock_policy, first_lock), self._lock(
+            lock_policy, second_lock
+        ):
+            self._loadState()
+            missing_keys = [key for key in keys if key not in self._state]
+            if missing_keys:
+                raise KeyError(
+                    f"Keys {missing_keys} not found in state for "
+                    + f"instance {self._instance_name}."
+                )
+
+            dest_state, dest_version = loadState(
+                self._redis_con,
+                dest_instance_name,
+                self._load_state_func,
+                schema=self._schema,
+                key_scheme=self._key_scheme,
+                on_decode_error=self._on_decode_error,
+            )
+            if dest_state is None:
+                raise ValueError(
+                    f"Instance {dest_instance_name} does not exist. Create it "
+                    + "before transferring keys to it."
+                )
+
+            moved = {key: self._state.pop(key) for key in keys}
+            dest_state.update(moved)
+
+            # Queue both writes on one transaction so there is no gap where
+            # the keys are in both instances or in neither. Both versions are
+            # watched so that a save by another writer aborts the transfer.
+            env = ":DEV" if os.getenv("MOTION_ENV", "prod") == "dev" else ""
+            stale_error = RuntimeError(
+                f"Error transferring keys from {self._instance_name} to "
+                + f"{dest_instance_name}: there was a newer state found."
+            )
+            pipeline = self._redis_con.pipeline()
+            try:
+                pipeline.watch(
+                    f"{self._key_scheme.version}{env}:{dest_instance_name}",
+                    f"{self._key_scheme.version}{env}:{self._instance_name}",
+                )
+                pipeline.multi()
+                new_dest_version = saveState(
+                    dest_state,
+                    dest_version,
+                    self._redis_con,
+                    dest_instance_name,
+                    self._save_state_func,
+                    max_state_bytes=self._max_state_bytes,
+                    max_state_keys=self._max_state_keys,
+                    schema=self._schema,
+                    updated_keys=moved.keys(),
+                    writer_id=self._writer_id,
+                    history_size=self._history_size,
+                    pipeline=pipeline,
+                    key_scheme=self._key_scheme,
+                )
+                new_version = saveState(
+                    self._state,
+                    self.version,  # type: ignore
+                    self._redis_con,
+                    self._instance_name,
+                    self._save_state_func,
+                    max_state_bytes=self._max_state_bytes,
+                    max_state_keys=self._max_state_keys,
+                    schema=self._schema,
+                    deleted_keys=moved.keys(),
+                    writer_id=self._writer_id,
+                    history_size=self._history_size,
+                    pipeline=pipeline,
+                    key_scheme=self._key_scheme,
+                )
+                if -1 in (new_dest_version, new_version):
+                    raise stale_error
+
+                pipeline.execute()
+            except Exception as e:
+                # Nothing was written, so drop the local changes
+                self.version = None
+                self._loadState()
+                if isinstance(e, redis.exceptions.WatchError):
+                    # Another writer saved one of the states before EXEC
+                    raise stale_error from e
+                raise
+            finally:
+                pipeline.reset()
+
+            self.version = new_version
+
+    def _checkHistoryVersion(self, version: int, op_name: str) -> int:
+        if not self._history_size:
+            raise ValueError(
+                f"History is not kept for {self._component_name}. Create the "
+                + f"component with history_size to use {op_name}."
+            )
+
+        current_version = self._loadVersion()
+        if version < 1 or version > current_version:  # type: ignore
+            raise ValueError(
+                f"Version {version} does not exist for {self._instance_name}."
+                + f" The current version is {current_version}."
+            )
+
+        return current_version  # type: ignore
+
+    def _valueAtVersion(self, key: str, version: int) -> Tuple[bool, Any]:
+        history = loadKeyHistory(
+            self._redis_con, self._instance_name, key, key_scheme=self._key_scheme
+        )
+        older_entries = [entry for entry in history if entry["version"] <= version]
+        if not older_entries:
+            # If the history was trimmed, the value at this version is gone
+            if len(history) >= self._history_size:  # type: ignore
+                raise ValueError(
+                    f"Version {version} of key `{key}` is older than the "
+                    + f"{self._history_size} values kept for "
+                    + f"{self._instance_name}."
+                )
+            return False, None
+
+        entry = older_entries[-1]
+        if entry.get("deleted"):
+            return False, None
+        return True, entry["value"]
+
+    def _getAtVersion(self, key: str, version: int, default_value: Any) -> Any:
+        self._checkHistoryVersion(version, "get_at_version")
+        found, value = self._valueAtVersion(key, version)
+        return value if found else default_value
+
+    def _rollback(self, version: int, lock_policy: Optional[LockPolicy] = None) -> int:
+        with self._lock(lock_policy):
+            self._loadState()
+            if self._checkHistoryVersion(version, "rollback") == version:
+                return self.version  # type: ignore
+
+            # Keys deleted since the version only show up in the history
+            keys = set(self._state.keys()) | set(
+                loadHistoryKeys(
+                    self._redis_con, self._instance_name, key_scheme=self._key_scheme
+                )
+            )
+            restored_state = State(
+                self._component_name, self._instance_id, {}, schema=self._schema
+            )
+            for key in keys:
+                if key in self._state and not loadKeyHistory(
+                    self._redis_con,
+                    self._instance_name,
+                    key,
+                    key_scheme=self._key_scheme,
+                ):
+                    raise ValueError(
+                        f"Key `{key}` has no history for {self._instance_name}, "
+                        + "so it cannot be rolled back."
+                    )
+
+                found, value = self._valueAtVersion(key, version)
+                if found:
+                    restored_state[key] = value
+
+            deleted_keys = set(self._state.keys()) - set(restored_state.keys())
+            self._setState(restored_state)
+
+            # The rollback is saved as a new version, like any other write
+            self._saveState(
+                self._state,
+                updated_keys=restored_state.keys(),
+                deleted_keys=deleted_keys,
+            )
+
+        return self.version  # type: ignore
+
+    def _subscribe(self, key_pattern: str, callback: Callable) -> None:
+        self._subscriptions.append((key_pattern, callback))
+        if self._events_thread is not None:
+            return
+
+        # Subscribe before returning so no changes after this call are missed
+        pubsub = self._redis_con.pubsub()
+        pubsub.subscribe(self.__events_channel)
+        self._events_thread = threading.Thread(
+            target=self._listenForEvents, args=(pubsub,), daemon=True
+        )
+        self._events_thread.start()
+
+    def _listenForEvents(self, pubsub: Any) -> None:
+        while not self._events_stop.is_set():
+            message = pubsub.get_message(ignore_subscribe_messages=True, timeout=1.0)
+            if message is None or message["type"] != "message":
+                continue
+
+            event = json.loads(message["data"])
+            changes = [(key, False) for key in event["updated_keys"]] + [
+                (key, True) for key in event["deleted_keys"]
+            ]
+            for key, deleted in changes:
+                for key_pattern, callback in list(self._subscriptions):
+                    if not fnmatch.fnmatchcase(key, key_pattern):
+                        continue
+
+                    try:
+                        callback(
+                            {
+                                "key": key,
+                                "version": event["version"],
+                                "writer": event["writer"],
+                                "deleted": deleted,
+                            }
+                        )
+                    except Exception as e:
+                        logger.error(
+                            f"Error in subscription callback for {key}: {e}",
+                            exc_info=True,
+                        )
+
+        pubsub.close()
+
+    def _accessReport(self) -> Dict[str, int]:
+        self._flushAccessCounts(force=True)
+        counts = {
+            key.decode("utf-8"): int(count)
+            for key, count in self._redis_con.hgetall(self.__access_key).items()
+        }
+        return dict(sorted(counts.items(), key=lambda item: -item[1]))
+
+    def _loadSchemaVersion(self) -> Optional[int]:
+        redis_sv = self._redis_con.get(self.__schema_version_key)
+        return int(redis_sv) if redis_sv is not None else None
 
-    def _saveState(self, new_state: State) -> None:
+    def _checkSchemaVersion(self) -> None:
+        if self._loadSchemaVersion() == self._schema_version:
+            return
+
+        with self._lock():
+            # Another process may have upgraded the instance while we
+            # waited for the lock
+            stored_version = self._loadSchemaVersion()
+            if stored_version == self._schema_version:
+                return
+
+            if self._schema_mismatch_func is not None:
+                self._schema_mismatch_func(
+                    self._instance_id, stored_version, self._schema_version
+                )
+            else:
+                logger.warning(
+                    f"Stored schema version {stored_version} for "
+                    + f"{self._instance_name} differs from the declared "
+                    + f"schema_version {self._schema_version}."
+                )
+
+            if self._upgrade_state_func is not None:
+                self._upgradeState(stored_version)
+
+            self._redis_con.set(self.__schema_version_key, self._schema_version)
+
+    def _upgradeState(self, stored_version: Optional[int]) -> None:
+        self._loadState()
+        try:
+            upgraded_state = self._upgrade_state_func(  # type: ignore
+                self._state, stored_version
+            )
+            if not isinstance(upgraded_state, dict):
+                raise TypeError("upgrade_state must return a dict.")
+        except Exception:
+            # Drop anything the hook changed in place
+            self.version = None
+            raise
+
+        new_state = State(
+            self._component_name, self._instance_id, {}, schema=self._schema
+        )
+        new_state.update(upgraded_state)
+        deleted_keys = set(self._state.keys()) - set(new_state.keys())
+        self._saveState(
+            new_state, updated_keys=new_state.keys(), deleted_keys=deleted_keys
+        )
+        self._setState(new_state)
+
+    def _saveState(
+        self,
+        new_state: State,
+        updated_keys: Optional[Iterable[str]] = None,
+        deleted_keys: Optional[Iterable[str]] = None,
+    ) -> None:
         assert self.version is not None, "Version should not be None."
 
         # Save state to redis
-        new_version = saveState(
-            new_state,
-            self.version,
-            self._redis_con,
-            self._instance_name,
-            self._save_state_func,
-        )
+        op_info: Dict[str, Any] = {}
+        try:
+            new_version = saveState(
+                new_state,
+                self.version,
+                self._redis_con,
+                self._instance_name,
+                self._save_state_func,
+                max_state_bytes=self._max_state_bytes,
+                max_state_keys=self._max_state_keys,
+                schema=self._schema,
+                updated_keys=updated_keys,
+                deleted_keys=deleted_keys,
+                writer_id=self._writer_id,
+                history_size=self._history_size,
+                key_scheme=self._key_scheme,
+                op_info=op_info,
+            )
+        except (ValueError, SchemaError):
+            # The write was rejected, so drop the local changes by
+            # reloading the state that is in redis
+            self.version = None
+            self._loadState()
+            raise
+
+        self._recordOp(op_info)
         if new_version == -1:
             logger.error(
                 f"Error saving state to Redis for {self._instance_name}:"
@@ -284,14 +847,19 @@ class Executor:
         self.route_dict_for_fit = {}
         self.channel_dict_for_fit = {}
         self.queue_ids_for_fit = []
-        for rkey, routes in self._update_routes.items():
-            for udf_name, route in routes.items():
-                queue_id = self._get_queue_identifier(rkey, udf_name)
-                self.queue_ids_for_fit.append(queue_id)
-                self.route_dict_for_fit[queue_id] = route
-                self.channel_dict_for_fit[queue_id] = self._get_channel_identifier(
-                    rkey, udf_name
-                )
+        # The update task pops from the first non-empty queue in this list,
+        # so all high priority queues go first
+        for high_priority in [True, False]:
+            for rkey, routes in self._update_routes.items():
+                for udf_name, route in routes.items():
+                    queue_id = self._get_queue_identifier(
+                        rkey, udf_name, high_priority
+                    )
+                    self.queue_ids_for_fit.append(queue_id)
+                    self.route_dict_for_fit[queue_id] = route
+                    self.channel_dict_for_fit[
+                        queue_id
+                    ] = self._get_channel_identifier(rkey, udf_name)
 
         self.worker_task = None
         if self.queue_ids_for_fit:
@@ -303,19 +871,32 @@ class Executor:
                 queue_identifiers=self.queue_ids_for_fit,
                 channel_identifiers=self.channel_dict_for_fit,
                 lock_identifier=self.__lock_prefix,
+                queue_prefix=self.__queue_prefix,
                 redis_params=self._redis_params.dict(),
                 running=self.running,
                 victoria_metrics_url=self.victoria_metrics_url,
+                max_state_bytes=self._max_state_bytes,
+                max_state_keys=self._max_state_keys,
+                schema=self._schema,
+                writer_id=self._writer_id,
+                history_size=self._history_size,
+                key_scheme=self._key_scheme,
+                on_decode_error=self._on_decode_error,
             )
             self.worker_task.start()  # type: ignore
 
         # Set up a monitor thread
-        self.stop_event = threading.Event()
         self.monitor_thread = threading.Thread(
             target=self._monitor_process, daemon=True
         )
         self.monitor_thread.start()
 
+        # Set up a thread that applies scheduled writes once they are due
+        self.scheduler_thread = threading.Thread(
+            target=self._applyScheduledWrites, daemon=True
+        )
+        self.scheduler_thread.start()
+
     def _monitor_process(self) -> None:
         if not self.worker_task:
             return
@@ -341,8 +922,16 @@ class Executor:
                     queue_identifiers=self.queue_ids_for_fit,
                     channel_identifiers=self.channel_dict_for_fit,
                     lock_identifier=self.__lock_prefix,
+                    queue_prefix=self.__queue_prefix,
                     redis_params=self._redis_params.dict(),
                     running=self.running,
+                    max_state_bytes=self._max_state_bytes,
+                    max_state_keys=self._max_state_keys,
+                    schema=self._schema,
+                    writer_id=self._writer_id,
+                    history_size=self._history_size,
+                    key_scheme=self._key_scheme,
+                    on_decode_error=self._on_decode_error,
                 )
                 self.worker_task.start()  # type: ignore
 
@@ -352,21 +941,223 @@ class Executor:
             # Sleep for a minute
             self.stop_event.wait(60)
 
-    def _get_queue_identifier(self, route_key: str, udf_name: str) -> str:
+    def _scheduleWrite(self, state_update: Dict[str, Any], at: float) -> None:
+        if not isinstance(state_update, dict):
+            raise TypeError("State should be a dict.")
+
+        # Reject bad writes now rather than when they are due
+        if self._schema is not None:
+            validate_state(self._instance_name, state_update, self._schema)
+
+        entry = cloudpickle.dumps(
+            {"identifier": str(uuid4()), "state_update": state_update}
+        )
+        self._redis_con.zadd(self.__scheduled_key, {entry: at})
+
+    def _applyScheduledWrites(self) -> None:
+        while not self.stop_event.wait(SCHEDULED_WRITE_INTERVAL):
+            try:
+                due = self._redis_con.zrangebyscore(
+                    self.__scheduled_key, "-inf", time.time(), withscores=True
+                )
+                for entry, at in due:
+                    # Every open handle polls, so the entry is applied under
+                    # the lock, and only if no other handle removed it yet.
+                    # It is removed after the write, so if this process dies
+                    # in between, the next poll applies it again
+                    with self._lock(DEFAULT_LOCK_POLICY):
+                        if self._redis_con.zscore(self.__scheduled_key, entry) != at:
+                            continue
+
+                        self._updateState(
+                            cloudpickle.loads(entry)["state_update"], use_lock=False
+                        )
+                        self._redis_con.eval(
+                            REMOVE_SCHEDULED_WRITE_SCRIPT,
+                            1,
+                            self.__scheduled_key,
+                            entry,
+                            at,
+                        )
+            except Exception:
+                logger.error("Error applying scheduled writes.", exc_info=True)
+
+    def _get_queue_identifier(
+        self, route_key: str, udf_name: str, high_priority: bool = False
+    ) -> str:
         """Gets the queue identifier for a given route key and UDF name."""
+        if high_priority:
+            return f"{self.__queue_prefix}/priority/{route_key}/{udf_name}"
         return f"{self.__queue_prefix}/{route_key}/{udf_name}"
 
     def _get_channel_identifier(self, route_key: str, udf_name: str) -> str:
         """Gets the channel identifier for a given route key and UDF name."""
         return f"{self.__channel_prefix}/{route_key}/{udf_name}"
 
+    def _idempotencyKey(self, flow_key: str, idempotency_key: str) -> str:
+        return f"{self.__queue_prefix}/idempotency/{flow_key}/{idempotency_key}"
+
+    def _duplicateRun(self, flow_key: str, idempotency_key: str) -> DuplicateRunError:
+        return DuplicateRunError(
+            f"Skipped update ops for {flow_key} in {self._instance_name}: "
+            + f"idempotency key {idempotency_key} was already used."
+        )
+
+    def _claimIdempotencyKey(self, flow_key: str, idempotency_key: str) -> str:
+        # Flushed update ops run right away instead of being queued, so the
+        # key is claimed up front and released if they fail
+        claim_key = self._idempotencyKey(flow_key, idempotency_key)
+        if not self._redis_con.set(claim_key, 1, nx=True, ex=DEFAULT_KEY_TTL):
+            raise self._duplicateRun(flow_key, idempotency_key)
+        return claim_key
+
+    def _pushUpdates(
+        self,
+        flow_key: str,
+        queued: List[Tuple[str, bytes, Callable]],
+        idempotency_key: Optional[str],
+    ) -> None:
+        if idempotency_key is None:
+            for queue_identifier, raw_item, _ in queued:
+                self._redis_con.rpush(queue_identifier, raw_item)
+        elif not self._redis_con.eval(
+            ENQUEUE_ONCE_SCRIPT,
+            1 + len(queued),
+            self._idempotencyKey(flow_key, idempotency_key),
+            *[queue_identifier for queue_identifier, _, _ in queued],
+            DEFAULT_KEY_TTL,
+            *[raw_item for _, raw_item, _ in queued],
+        ):
+            raise self._duplicateRun(flow_key, idempotency_key)
+
+        for queue_identifier, _, func in queued:
+            # If the func has a discard_after attribute, delete
+            # old items in a queue
+            if func._discard_after is not None:  # type: ignore
+                if func._discard_policy == DiscardPolicy.NUM_NEW_UPDATES:  # type: ignore # noqa: E501
+                    # Get the length of the queue
+                    queue_length = self._redis_con.llen(queue_identifier)
+                    # If the queue length is greater than the
+                    # discard_after attribute, delete the oldest
+                    # (queue_length - discard_after) items
+                    if queue_length > func._discard_after:  # type: ignore
+                        self._redis_con.ltrim(
+                            queue_identifier,
+                            queue_length - func._discard_after,  # type: ignore
+                            -1,
+                        )
+
+                elif func._discard_policy == DiscardPolicy.SECONDS:  # type: ignore # noqa: E501
+                    # Need to delete items that are older than
+                    # discard_after seconds
+                    # Can just do this in the update task
+                    pass
+
+    def _queuedUpdates(
+        self, flow_key: str, n: Optional[int] = None
+    ) -> List[Tuple[str, bytes, Dict[str, Any]]]:
+        # Reads without popping, so the update task is unaffected. Flush
+        # markers are internal and left out
+        queued = []
+        for udf_name, high_priority in itertools.product(
+            self._update_routes.get(flow_key, {}).keys(), [True, False]
+        ):
+            queue_identifier = self._get_queue_identifier(
+                flow_key, udf_name, high_priority
+            )
+            raw_items = self._redis_con.lrange(
+                queue_identifier, 0, -1 if n is None else n - 1
+            )
+            for raw_item in raw_items:
+                item = cloudpickle.loads(raw_item)
+                if item["identifier"].startswith("NOOP_"):
+                    continue
+                queued.append(
+                    (
+                        queue_identifier,
+                        raw_item,
+                        {
+                            "identifier": item["identifier"],
+                            "udf_name": udf_name,
+                            "props": item["props"],
+                            "expire_at": item["expire_at"],
+                            "high_priority": high_priority,
+                        },
+                    )
+                )
+
+        return queued
+
+    def _removeQueuedUpdate(self, flow_key: str, identifier: str) -> bool:
+        for queue_identifier, raw_item, item in self._queuedUpdates(flow_key):
+            if item["identifier"] == identifier:
+                # Each queued item has a unique identifier, so this removes
+                # exactly the one item, unless the update task popped it first
+                return self._redis_con.lrem(queue_identifier, 1, raw_item) > 0
+
+        return False
+
+    def _requeueInflight(self) -> int:
+        return requeue_inflight(self._redis_con, self.__queue_prefix)
+
+    def _drain(self, timeout: Optional[float] = None) -> bool:
+        queue_identifiers = [
+            self._get_queue_identifier(rkey, udf_name, high_priority)
+            for high_priority in [True, False]
+            for rkey, routes in self._update_routes.items()
+            for udf_name in routes.keys()
+        ]
+        deadline = None if timeout is None else time.time() + timeout
+
+        # Items of dead update tasks would otherwise never finish
+        self._requeueInflight()
+        while True:
+            pipeline = self._redis_con.pipeline()
+            for queue_identifier in queue_identifiers:
+                pipeline.llen(queue_identifier)
+            queued = sum(pipeline.execute()) if queue_identifiers else 0
+            if not queued and not any(
+                self._redis_con.scan_iter(f"{self.__queue_prefix}/inflight/*/*")
+            ):
+                return True
+
+            if deadline is not None and time.time() >= deadline:
+                return False
+            time.sleep(0.05)
+
     def shutdown(self, is_open: bool, wait_for_logging_threads: bool) -> None:
+        # A failed flush is logged, and the rest of the handle still shuts down
+        try:
+            self._flushWrites()
+        except Exception as e:
+            logger.error(
+                f"Error flushing buffered writes for {self._instance_name}: {e}",
+                exc_info=True,
+            )
+        try:
+            self._flushAccessCounts(force=True)
+        except Exception as e:
+            logger.error(
+                f"Error flushing access counts for {self._instance_name}: {e}",
+                exc_info=True,
+            )
+        for redis_con in self._timeout_cons.values():
+            redis_con.close()
+
+        # Stop delivering state change events
+        self._events_stop.set()
+        if self._events_thread is not None:
+            self._events_thread.join()
+            self._events_thread = None
+
         if self.disable_update_task:
+            self._stopBackgroundThreads()
             if self._redis_con:
                 self._redis_con.close()
             return
 
         if not self.running.value:
+            self._stopBackgroundThreads()
             if self._redis_con:
                 self._redis_con.close()
             return
@@ -388,38 +1179,50 @@ class Executor:
             if self.worker_task and self.worker_task.is_alive():  # type: ignore
                 self.worker_task.join()  # type: ignore
 
+        self._stopBackgroundThreads()
+
         # Shut down threadpool for writing to Redis and logging
         self.tp.shutdown(wait=wait_for_logging_threads)
 
         self._redis_con.close()
 
-        self.monitor_thread.join()
-
         # Delete self.running
         self.running = None
         del self.running
 
+    def _stopBackgroundThreads(self) -> None:
+        # The monitor and scheduler threads only run with the update task
+        self.stop_event.set()
+        for thread in [self.scheduler_thread, self.monitor_thread]:
+            if thread is not None:
+                thread.join()
+
     def _updateState(
         self,
         new_state: Dict[str, Any],
         force_update: bool = True,
         use_lock: bool = True,
-    ) -> None:
+        lock_policy: Optional[LockPolicy] = None,
+    ) -> Optional[int]:
         if not new_state:
-            return
+            return self.version
 
         if not isinstance(new_state, dict):
             raise TypeError("State should be a dict.")
 
+        if self._write_coalesce_ms and use_lock and lock_policy is None:
+            self._coalesceWrite(new_state)
+            return None
+
         # Get latest state
         if use_lock:
-            with self._redis_con.lock(self.__lock_prefix, timeout=120):
+            with self._lock(lock_policy):
                 if force_update:
                     self._loadState()
                 self._state.update(new_state)
 
                 # Save state to redis
-                self._saveState(self._state)
+                self._saveState(self._state, updated_keys=new_state.keys())
 
         else:
             if force_update:
@@ -427,7 +1230,421 @@ class Executor:
             self._state.update(new_state)
 
             # Save state to redis
-            self._saveState(self._state)
+            self._saveState(self._state, updated_keys=new_state.keys())
+
+        return self.version
+
+    def _coalesceWrite(self, new_state: Dict[str, Any]) -> None:
+        # Reject bad writes now rather than when the buffer is flushed
+        if self._schema is not None:
+            validate_state(self._instance_name, new_state, self._schema)
+
+        with self._pending_lock:
+            self._pending_writes.update(new_state)
+            if self.version is not None:
+                dict.update(self._state, new_state)
+            if self._flush_timer is None:
+                self._flush_timer = threading.Timer(
+                    self._write_coalesce_ms / 1000,  # type: ignore
+                    self._flushWritesInBackground,
+                )
+                self._flush_timer.daemon = True
+                self._flush_timer.start()
+
+    def _flushWrites(self) -> Optional[int]:
+        with self._pending_lock:
+            if self._flush_timer is not None:
+                self._flush_timer.cancel()
+                self._flush_timer = None
+            pending_writes = self._pending_writes
+            self._pending_writes = {}
+
+        if not pending_writes:
+            return None
+
+        try:
+            with self._lock():
+                self._loadState()
+                self._state.update(pending_writes)
+                self._saveState(self._state, updated_keys=pending_writes.keys())
+                return self.version
+        except Exception:
+            # Put the writes back so the next flush retries them. Writes
+            # buffered while this flush ran are newer, so they win.
+            with self._pending_lock:
+                pending_writes.update(self._pending_writes)
+                self._pending_writes = pending_writes
+            raise
+
+    def _flushAndWait(self, replicas: int = 0, timeout: float = 1) -> int:
+        if replicas < 0 or timeout <= 0:
+            raise ValueError("replicas must be non-negative and timeout positive.")
+
+        if not replicas:
+            new_version = self._flushWrites()
+            return new_version if new_version is not None else self.version  # type: ignore
+
+        # WAIT only counts the writes sent on its own connection, so the
+        # flush and the WAIT share one checked out connection
+        redis_con = redis.Redis(
+            connection_pool=self._redis_con.connection_pool,
+            single_connection_client=True,
+        )
+        self._local.redis_con = redis_con
+        try:
+            new_version = self._flushWrites()
+            num_acked = redis_con.wait(replicas, int(timeout * 1000))
+        finally:
+            self._local.redis_con = None
+            redis_con.close()
+
+        if num_acked < replicas:
+            raise TimeoutError(
+                f"Only {num_acked} of {replicas} replicas acknowledged the "
+                + f"writes to {self._instance_name} within {timeout} seconds."
+            )
+
+        return new_version if new_version is not None else self.version  # type: ignore
+
+    def _flushWritesInBackground(self) -> None:
+        try:
+            self._flushWrites()
+        except Exception as e:
+            logger.error(
+                f"Error flushing buffered writes for {self._instance_name}: {e}",
+                exc_info=True,
+            )
+
+    def _popState(
+        self,
+        key: str,
+        default_value: Any = None,
+        lock_policy: Optional[LockPolicy] = None,
+    ) -> Any:
+        with self._lock(lock_policy):
+            self._loadState()
+            if key not in self._state:
+                return default_value
+
+            value = self._state.pop(key)
+
+            # Save state to redis
+            self._saveState(self._state, deleted_keys=[key])
+
+        return value
+
+    def _renameKeys(
+        self, mapping: Dict[str, str], lock_policy: Optional[LockPolicy] = None
+    ) -> int:
+        with self._lock(lock_policy):
+            self._loadState()
+            missing_keys = [key for key in mapping if key not in self._state]
+            if missing_keys:
+                raise KeyError(
+                    f"Keys {missing_keys} not found in state for "
+                    + f"instance {self._instance_name}."
+                )
+
+            # Renaming onto a key that stays in the state would overwrite it
+            existing_keys = [
+                new_key
+                for new_key in mapping.values()
+                if new_key in self._state and new_key not in mapping
+            ]
+            if len(set(mapping.values())) != len(mapping) or existing_keys:
+                raise ValueError(
+                    f"Renaming {mapping} would overwrite keys in the state for "
+                    + f"instance {self._instance_name}."
+                )
+
+            values = {new_key: self._state.pop(key) for key, new_key in mapping.items()}
+            self._state.update(values)
+            self._saveState(
+                self._state,
+                updated_keys=values.keys(),
+                deleted_keys=set(mapping.keys()) - set(values.keys()),
+            )
+
+        return self.version  # type: ignore
+
+    def _applyOps(
+        self, ops: List[Tuple[Any, ...]], lock_policy: Optional[LockPolicy] = None
+    ) -> List[Any]:
+        # Check every op's form before taking the lock, so a malformed op
+        # late in the list doesn't fail after earlier ops ran
+        op_lengths = {"set": (3,), "delete": (2,), "incr": (2, 3)}
+        for op in ops:
+            if not op or len(op) not in op_lengths.get(op[0], ()):
+                raise ValueError(
+                    f"Op {op!r} should be ('set', key, value), "
+                    + "('delete', key), or ('incr', key[, amount])."
+                )
+
+        with self._lock(lock_policy):
+            self._loadState()
+
+            # Run the ops on a copy, so a bad op leaves the state untouched
+            new_state = dict.copy(self._state)
+            touched_keys = []
+            results = []
+            for op in ops:
+                name, key, *args = op
+                if name == "set":
+                    new_state[key] = args[0]
+                    results.append(None)
+                elif name == "delete":
+                    results.append(new_state.pop(key, None))
+                else:
+                    new_state[key] = new_state.get(key, 0) + (args[0] if args else 1)
+                    results.append(new_state[key])
+                touched_keys.append(key)
+
+            updated_keys = {key for key in touched_keys if key in new_state}
+            deleted_keys = {
+                key
+                for key in touched_keys
+                if key not in new_state and key in self._state
+            }
+            if not updated_keys and not deleted_keys:
+                return results
+
+            for key in deleted_keys:
+                self._state.pop(key)
+            self._state.update({key: new_state[key] for key in updated_keys})
+            self._saveState(
+                self._state, updated_keys=updated_keys, deleted_keys=deleted_keys
+            )
+
+        return results
+
+    def _tempKeys(self) -> List[Tuple[str, float]]:
+        # Cached serve results and idempotency keys expire. Only their
+        # prefixes are scanned, since update task heartbeats under the queue
+        # prefix also expire but aren't keys of this instance
+        keys = [
+            key
+            for pattern in [
+                f"{self.__cache_result_prefix}/*",
+                f"{self.__queue_prefix}/idempotency/*",
+            ]
+            for key in self._redis_con.scan_iter(pattern)
+        ]
+        pipeline = self._redis_con.pipeline(transaction=False)
+        for key in keys:
+            pipeline.pttl(key)
+
+        temp_keys = [
+            (key.decode("utf-8") if isinstance(key, bytes) else key, pttl / 1000)
+            for key, pttl in zip(keys, pipeline.execute())
+            if pttl >= 0
+        ]
+        return sorted(temp_keys, key=lambda temp_key: temp_key[1])
+
+    def _initKeys(
+        self, defaults: Dict[str, Any], lock_policy: Optional[LockPolicy] = None
+    ) -> Dict[str, Any]:
+        with self._lock(lock_policy):
+            self._loadState()
+            missing = {
+                key: value for key, value in defaults.items() if key not in self._state
+            }
+            if missing:
+                self._state.update(missing)
+                self._saveState(self._state, updated_keys=missing.keys())
+
+            return {key: dict.__getitem__(self._state, key) for key in defaults}
+
+    def _patchValue(
+        self,
+        key: str,
+        patch: List[Dict[str, Any]],
+        lock_policy: Optional[LockPolicy] = None,
+    ) -> Tuple[Any, int]:
+        with self._lock(lock_policy):
+            self._loadState()
+            if key not in self._state:
+                raise KeyError(
+                    f"Key {key} not found in state for instance {self._instance_name}."
+                )
+
+            new_value = apply_json_patch(dict.__getitem__(self._state, key), patch)
+            self._state[key] = new_value
+            self._saveState(self._state, updated_keys=[key])
+
+        return new_value, self.version  # type: ignore
+
+    def _getAndUpdate(
+        self,
+        key: str,
+        func: Callable[[Any], Any],
+        default_value: Any = None,
+        timeout: float = 60,
+        lock_policy: Optional[LockPolicy] = None,
+    ) -> Any:
+        if timeout <= 0:
+            raise ValueError("timeout must be positive.")
+
+        with self._lock(lock_policy):
+            self._loadState()
+            value = dict.get(self._state, key, default_value)
+
+            # Run the callable in a separate thread so that a hung callable
+            # can't hold the lock past the timeout
+            result: Dict[str, Any] = {}
+
+            def run_func() -> None:
+                try:
+                    result["value"] = func(value)
+                except BaseException as e:
+                    result["error"] = e
+
+            func_thread = threading.Thread(target=run_func, daemon=True)
+            func_thread.start()
+            func_thread.join(timeout)
+
+            if func_thread.is_alive() or "error" in result:
+                # Drop anything the callable changed in place
+                self.version = None
+                self._loadState()
+                if "error" in result:
+                    raise result["error"]
+                raise TimeoutError(
+                    f"Update of key {key} for {self._instance_name} did not "
+                    + f"finish within {timeout} seconds. State was not saved."
+                )
+
+            self._state[key] = result["value"]
+            self._saveState(self._state, updated_keys=[key])
+
+        return result["value"]
+
+    def _digest(self) -> Optional[str]:
+        state_keys = [f"{self._key_scheme.state}:{self._instance_name}"]
+        if os.getenv("MOTION_ENV", "prod") == "dev":
+            state_keys.insert(
+                0, f"{self._key_scheme.state}:DEV:{self._instance_name}"
+            )
+
+        digest = self._redis_con.eval(
+            STATE_DIGEST_SCRIPT, len(state_keys), *state_keys
+        )
+        return digest.decode("utf-8") if digest else None
+
+    def _getRawState(self) -> bytes:
+        raw_state, _ = loadRawState(
+            self._redis_con, self._instance_name, key_scheme=self._key_scheme
+        )
+        if raw_state is None:
+            raise ValueError(f"State for {self._instance_name} does not exist.")
+        return raw_state
+
+    def _setRawState(
+        self, raw_state: bytes, lock_policy: Optional[LockPolicy] = None
+    ) -> int:
+        if not isinstance(raw_state, bytes):
+            raise TypeError("Raw state must be bytes.")
+
+        # Reject bytes this component couldn't load back
+        try:
+            state = deserializeState(
+                raw_state,
+                self._instance_name,
+                self._load_state_func,
+                schema=self._schema,
+            )
+        except Exception as e:
+            raise ValueError(
+                f"Raw state for {self._instance_name} can't be deserialized: {e}"
+            ) from e
+
+        with self._lock(lock_policy):
+            old_raw_state, version = loadRawState(
+                self._redis_con, self._instance_name, key_scheme=self._key_scheme
+            )
+
+            # The stored state may be an undecodable one being repaired, in
+            # which case there are no old keys to mark as deleted
+            old_keys: Set[str] = set()
+            if old_raw_state is not None:
+                with contextlib.suppress(Exception):
+                    old_keys = set(
+                        deserializeState(
+                            old_raw_state, self._instance_name, self._load_state_func
+                        ).keys()
+                    )
+
+            op_info: Dict[str, Any] = {}
+            new_version = saveState(
+                state,
+                version,
+                self._redis_con,
+                self._instance_name,
+                self._save_state_func,
+                max_state_bytes=self._max_state_bytes,
+                max_state_keys=self._max_state_keys,
+                schema=self._schema,
+                updated_keys=state.keys(),
+                deleted_keys=old_keys - set(state.keys()),
+                writer_id=self._writer_id,
+                history_size=self._history_size,
+                key_scheme=self._key_scheme,
+                op_info=op_info,
+                serialized_state=raw_state,
+            )
+            self._recordOp(op_info)
+
+        # The local copy is now stale, so it is reloaded on the next read
+        self.version = None
+        return new_version
+
+    def _verify(self, heal: bool = False) -> Dict[str, Any]:
+        cached_version = self.version
+        stored_state, stored_version = loadState(
+            self._redis_con,
+            self._instance_name,
+            self._load_state_func,
+            schema=self._schema,
+            key_scheme=self._key_scheme,
+            on_decode_error=self._on_decode_error,
+        )
+
+        mismatched_keys = []
+        if cached_version is not None and stored_state is not None:
+            with self._pending_lock:
+                pending_keys = set(self._pending_writes.keys())
+
+            # Buffered writes aren't in redis yet, so they are expected to
+            # differ
+            for key in (set(self._state) | set(stored_state)) - pending_keys:
+                if key not in self._state or key not in stored_state:
+                    mismatched_keys.append(key)
+                    continue
+
+                cached_value = dict.__getitem__(self._state, key)
+                stored_value = dict.__getitem__(stored_state, key)
+                if cached_value is not stored_value and cloudpickle.dumps(
+                    cached_value
+                ) != cloudpickle.dumps(stored_value):
+                    mismatched_keys.append(key)
+
+        report = {
+            "cached_version": cached_version,
+            "stored_version": stored_version,
+            "mismatched_keys": sorted(mismatched_keys, key=str),
+        }
+
+        if heal and (cached_version != stored_version or mismatched_keys):
+            self.version = None
+            self._loadState()
+
+        return report
+
+    def _loadKeyMeta(
+        self, keys: Optional[List[str]] = None
+    ) -> Dict[str, Dict[str, Any]]:
+        return loadKeyMeta(
+            self._redis_con, self._instance_name, keys, key_scheme=self._key_scheme
+        )
 
     def _enqueue_and_trigger_update(
         self,
@@ -435,12 +1652,21 @@ class Executor:
         props: Properties,
         flush_update: bool,
         route_hit: bool,
+        high_priority: bool = False,
+        idempotency_key: Optional[str] = None,
     ) -> bool:
         # Run the update routes
         # Enqueue results into update queues
         if key in self._update_routes.keys():
             route_hit = True
 
+            claim_key = (
+                self._claimIdempotencyKey(key, idempotency_key)
+                if flush_update and idempotency_key is not None
+                else None
+            )
+            queued: List[Tuple[str, bytes, Callable]] = []
+
             # update_events = UpdateEventGroup(key)
             for update_udf_name in self._update_routes[key].keys():
                 # If flushing update, just run the route
@@ -492,6 +1718,10 @@ class Executor:
                                     route.udf.__name__,
                                 )
 
+                            # The update ops didn't all run, so a retry may
+                            if claim_key is not None:
+                                self._redis_con.delete(claim_key)
+
                             raise RuntimeError(
                                 "Error running update route in main process: " + str(e)
                             )
@@ -507,7 +1737,7 @@ class Executor:
                     func = self._update_routes[key][update_udf_name].udf
 
                     queue_identifier: str = self._get_queue_identifier(
-                        key, update_udf_name
+                        key, update_udf_name, high_priority
                     )
 
                     identifier = str(uuid4())
@@ -520,39 +1750,23 @@ class Executor:
                         else None
                     )  # type: ignore
 
-                    # Add to update queue
-                    self._redis_con.rpush(
-                        queue_identifier,
-                        cloudpickle.dumps(
-                            {
-                                "props": props,
-                                "identifier": identifier,
-                                "expire_at": expire_at,
-                            }
-                        ),
+                    queued.append(
+                        (
+                            queue_identifier,
+                            cloudpickle.dumps(
+                                {
+                                    "props": props,
+                                    "identifier": identifier,
+                                    "expire_at": expire_at,
+                                }
+                            ),
+                            func,
+                        )
                     )
 
-                    # If the func has a discard_after attribute, delete
-                    # old items in a queue
-                    if func._discard_after is not None:  # type: ignore
-                        if func._discard_policy == DiscardPolicy.NUM_NEW_UPDATES:  # type: ignore # noqa: E501
-                            # Get the length of the queue
-                            queue_length = self._redis_con.llen(queue_identifier)
-                            # If the queue length is greater than the
-                            # discard_after attribute, delete the oldest
-                            # (queue_length - discard_after) items
-                            if queue_length > func._discard_after:  # type: ignore
-                                self._redis_con.ltrim(
-                                    queue_identifier,
-                                    queue_length - func._discard_after,  # type: ignore
-                                    -1,
-                                )
-
-                        elif func._discard_policy == DiscardPolicy.SECONDS:  # type: ignore # noqa: E501
-                            # Need to delete items that are older than
-                            # discard_after seconds
-                            # Can just do this in the update task
-                            pass
+            # Add to update queues
+            if queued:
+                self._pushUpdates(key, queued, idempotency_key)
 
         return route_hit
 
@@ -562,12 +1776,21 @@ class Executor:
         props: Properties,
         flush_update: bool,
         route_hit: bool,
+        high_priority: bool = False,
+        idempotency_key: Optional[str] = None,
     ) -> bool:
         # Run the update routes
         # Enqueue results into update queues
         if key in self._update_routes.keys():
             route_hit = True
 
+            claim_key = (
+                self._claimIdempotencyKey(key, idempotency_key)
+                if flush_update and idempotency_key is not None
+                else None
+            )
+            queued: List[Tuple[str, bytes, Callable]] = []
+
             # update_events = UpdateEventGroup(key)
             for update_udf_name in self._update_routes[key].keys():
                 # If flushing update, just run the route
@@ -621,6 +1844,10 @@ class Executor:
                                     route.udf.__name__,
                                 )
 
+                            # The update ops didn't all run, so a retry may
+                            if claim_key is not None:
+                                self._redis_con.delete(claim_key)
+
                             raise RuntimeError(
                                 "Error running update route in main process: " + str(e)
                             )
@@ -634,7 +1861,7 @@ class Executor:
 
                     func = self._update_routes[key][update_udf_name].udf
                     queue_identifier: str = self._get_queue_identifier(
-                        key, update_udf_name
+                        key, update_udf_name, high_priority
                     )
 
                     identifier = str(uuid4())
@@ -647,39 +1874,23 @@ class Executor:
                         else None
                     )
 
-                    # Add to update queue
-                    self._redis_con.rpush(
-                        queue_identifier,
-                        cloudpickle.dumps(
-                            {
-                                "props": props,
-                                "identifier": identifier,
-                                "expire_at": expire_at,
-                            }
-                        ),
+                    queued.append(
+                        (
+                            queue_identifier,
+                            cloudpickle.dumps(
+                                {
+                                    "props": props,
+                                    "identifier": identifier,
+                                    "expire_at": expire_at,
+                                }
+                            ),
+                            func,
+                        )
                     )
 
-                    # If the func has a discard_after attribute, delete
-                    # old items in a queue
-                    if func._discard_after is not None:  # type: ignore
-                        if func._discard_policy == DiscardPolicy.NUM_NEW_UPDATES:  # type: ignore # noqa: E501
-                            # Get the length of the queue
-                            queue_length = self._redis_con.llen(queue_identifier)
-                            # If the queue length is greater than the
-                            # discard_after attribute, delete the oldest
-                            # (queue_length - discard_after) items
-                            if queue_length > func._discard_after:  # type: ignore
-                                self._redis_con.ltrim(
-                                    queue_identifier,
-                                    queue_length - func._discard_after,  # type: ignore
-                                    -1,
-                                )
-
-                        elif func._discard_policy == DiscardPolicy.SECONDS:  # type: ignore # noqa: E501
-                            # Need to delete items that are older than
-                            # discard_after seconds
-                            # Can just do this in the update task
-                            pass
+            # Add to update queues
+            if queued:
+                self._pushUpdates(key, queued, idempotency_key)
 
         return route_hit
 
@@ -726,6 +1937,8 @@ class Executor:
         ignore_cache: bool,
         force_refresh: bool,
         flush_update: bool,
+        high_priority: bool = False,
+        idempotency_key: Optional[str] = None,
     ) -> Generator[Any, None, None]:
         try:
             route_hit = False
@@ -796,9 +2009,13 @@ class Executor:
 
             # Run the update routes
             # Enqueue results into update queues
-            route_hit = self._enqueue_and_trigger_update(
-                key, props, flush_update, route_hit
-            )
+            try:
+                route_hit = self._enqueue_and_trigger_update(
+                    key, props, flush_update, route_hit, high_priority, idempotency_key
+                )
+            except DuplicateRunError as e:
+                e.serve_result = serve_result
+                raise
 
             if not route_hit:
                 raise KeyError(
@@ -832,6 +2049,8 @@ class Executor:
         ignore_cache: bool,
         force_refresh: bool,
         flush_update: bool,
+        high_priority: bool = False,
+        idempotency_key: Optional[str] = None,
     ) -> AsyncGenerator[Any, None]:
         try:
             route_hit = False
@@ -899,9 +2118,13 @@ class Executor:
 
             # Run the update routes
             # Enqueue results into update queues
-            route_hit = await self._async_enqueue_and_trigger_update(
-                key, props, flush_update, route_hit
-            )
+            try:
+                route_hit = await self._async_enqueue_and_trigger_update(
+                    key, props, flush_update, route_hit, high_priority, idempotency_key
+                )
+            except DuplicateRunError as e:
+                e.serve_result = serve_result
+                raise
 
             if not route_hit:
                 raise KeyError(
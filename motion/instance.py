@@ -1,6 +1,9 @@
 import atexit
+import copy
 import inspect
 import logging
+import random
+from types import MappingProxyType
 from typing import (
     Any,
     AsyncGenerator,
@@ -10,13 +13,23 @@ from typing import (
     Generator,
     List,
     Literal,
+    Mapping,
     Optional,
     Set,
+    Tuple,
+    Union,
 )
 
 from motion.execute import Executor
 from motion.route import Route
-from motion.utils import DEFAULT_KEY_TTL, configureLogging
+from motion.utils import (
+    DEFAULT_KEY_SCHEME,
+    DEFAULT_KEY_TTL,
+    KeyScheme,
+    LockPolicy,
+    RedisParams,
+    configureLogging,
+)
 
 logger = logging.getLogger(__name__)
 
@@ -47,8 +60,22 @@ class ComponentInstance:
         update_task_type: Literal["thread", "process"] = "thread",
         disable_update_task: bool = False,
         cache_ttl: int = DEFAULT_KEY_TTL,
+        max_state_bytes: Optional[int] = None,
+        max_state_keys: Optional[int] = None,
+        schema: Optional[Dict[str, Any]] = None,
+        schema_version: Optional[int] = None,
+        schema_mismatch_func: Optional[Callable] = None,
+        upgrade_state_func: Optional[Callable] = None,
+        track_access: bool = False,
+        history_size: Optional[int] = None,
+        key_scheme: KeyScheme = DEFAULT_KEY_SCHEME,
+        cache_returns: Literal["live", "copy"] = "live",
+        redis_params: Optional[RedisParams] = None,
+        on_decode_error: Union[str, Callable] = "raise",
         redis_socket_timeout: int = 60,
         flush_on_exit: bool = False,
+        writer_id: str = "",
+        write_coalesce_ms: Optional[int] = None,
     ):
         """Creates a new instance of a Motion component.
 
@@ -74,6 +101,7 @@ class ComponentInstance:
         # Create instance name
         self._instance_name = f"{self._component_name}__{instance_id}"
         self._cache_ttl = cache_ttl
+        self._cache_returns = cache_returns
 
         self.running = False
         self.disable_update_task = disable_update_task
@@ -87,6 +115,15 @@ class ComponentInstance:
         self._executor = Executor(
             self._instance_name,
             cache_ttl=self._cache_ttl,
+            max_state_bytes=max_state_bytes,
+            max_state_keys=max_state_keys,
+            schema=schema,
+            schema_version=schema_version,
+            schema_mismatch_func=schema_mismatch_func,
+            upgrade_state_func=upgrade_state_func,
+            track_access=track_access,
+            history_size=history_size,
+            key_scheme=key_scheme,
             init_state_func=init_state_func,
             init_state_params=init_state_params if init_state_params else {},
             save_state_func=save_state_func,
@@ -95,7 +132,11 @@ class ComponentInstance:
             update_routes=update_routes,
             update_task_type=update_task_type,
             disable_update_task=self.disable_update_task,
+            redis_params=redis_params,
+            on_decode_error=on_decode_error,
             redis_socket_timeout=redis_socket_timeout,
+            writer_id=writer_id,
+            write_coalesce_ms=write_coalesce_ms,
         )
         self.running = True
 
@@ -206,10 +247,12 @@ class ComponentInstance:
 
         self.running = False
 
-    def get_version(self) -> int:
+    def get_version(self, refresh: bool = False) -> int:
         """
         Gets the state version (might be outdated) currently being
-        used for serve ops.
+        used for serve ops. Pass refresh=True to read the current
+        version from Redis instead, e.g., when coordinating with
+        other processes that write to this instance.
 
         Usage:
         ```python
@@ -226,11 +269,31 @@ class ComponentInstance:
         if __name__ == "__main__":
             with C() as c_instance:
                 c_instance.get_version() # Returns 1 (first version)
+                c_instance.get_version(refresh=True) # Returns the
+                # latest version, including writes by other processes
         ```
+
+        Args:
+            refresh (bool, optional): Whether to read the version from
+                Redis rather than the cached one. This doesn't reload
+                the cached state. Defaults to False.
+
+        Returns:
+            int: The state version.
         """
+        if refresh:
+            version = self._executor._loadVersion()
+            if version is not None:
+                return version
+
         return self._executor.version  # type: ignore
 
-    def write_state(self, state_update: Dict[str, Any]) -> None:
+    def write_state(
+        self,
+        state_update: Dict[str, Any],
+        lock_policy: Optional[LockPolicy] = None,
+        timeout: Optional[float] = None,
+    ) -> Optional[int]:
         """Writes the state update to the component instance's state.
         If a update op is currently running, the state update will be
         applied after the update op is finished. Warning: this could
@@ -252,7 +315,8 @@ class ComponentInstance:
         if __name__ == "__main__":
             with C() as c_instance:
                 c_instance.read_state("value") # Returns 0
-                c_instance.write_state({"value": 1, "value2": 2})
+                c_instance.write_state({"value": 1, "value2": 2}) # Returns
+                # the new version, e.g., 2
                 c_instance.read_state("value") # Returns 1
                 c_instance.read_state("value2") # Returns 2
         ```
@@ -267,10 +331,75 @@ class ComponentInstance:
                 only need to set this to true if you are updating an
                 instance you connected to a while ago and might be
                 outdated. Defaults to False.
+            lock_policy (Optional[LockPolicy], optional): Defaults to waiting.
+            timeout (Optional[float], optional): Seconds that each Redis
+                command of this call, and the wait for the lock if no
+                lock_policy is given, may take. Defaults to None, which
+                uses the instance's `redis_socket_timeout`.
+
+        Raises:
+            redis.exceptions.LockError: If the lock could not be acquired
+                under the lock policy. The state is not updated.
+            redis.exceptions.TimeoutError: If a Redis command took longer
+                than the timeout.
+
+        Returns:
+            Optional[int]: Version of the state produced by this write, so
+            callers don't need a separate `get_version` call that may
+            already be stale. None if the write was buffered because the
+            instance was created with `write_coalesce_ms`; passing a
+            `lock_policy` or `timeout` skips the buffer and writes right
+            away. If a buffered flush fails, the writes stay buffered and
+            are retried by the next flush.
+        """
+        if timeout is not None and lock_policy is None:
+            lock_policy = LockPolicy.wait(timeout)
+
+        with self._executor._timeout(timeout):
+            return self._executor._updateState(state_update, lock_policy=lock_policy)
+
+    def schedule_write(self, state_update: Dict[str, Any], at: float) -> None:
+        """Schedules a state update to be written at a future time, e.g., to
+        flip a flag when a campaign starts. Scheduled writes are stored in
+        Redis and applied through the same locked write path as write_state
+        by whichever open handle of this instance sees them first, within
+        about a second of the scheduled time. A write is removed only after
+        it is applied, so one that fails, or whose handle dies mid-write,
+        stays scheduled and is applied on the next poll.
+
+        Every open handle polls Redis once a second, and handles created
+        with `disable_update_task=True` don't poll at all. If no polling
+        handle of the instance is open, scheduled writes wait in Redis
+        until one is.
+
+        Usage:
+        ```python
+        c_instance.schedule_write(
+            {"campaign_live": True}, at=time.time() + 3600
+        )
+        c_instance.read_state("campaign_live") # Returns False
+        # until the hour is up
+        ```
+
+        Args:
+            state_update (Dict[str, Any]): Dictionary of key-value pairs
+                to update the state with.
+            at (float): Unix timestamp to write the update at. Updates
+                scheduled in the past are written right away.
+
+        Raises:
+            TypeError: If state_update is not a dict.
+            SchemaError: If the component has a schema and the update does
+                not match it. Nothing is scheduled.
         """
-        self._executor._updateState(state_update)
+        self._executor._scheduleWrite(state_update, at)
 
-    def read_state(self, key: str, default_value: Optional[Any] = None) -> Any:
+    def read_state(
+        self,
+        key: str,
+        default_value: Optional[Any] = None,
+        timeout: Optional[float] = None,
+    ) -> Any:
         """Gets the current value for the key in the component instance's state.
 
         Usage:
@@ -298,13 +427,797 @@ class ComponentInstance:
             key (str): Key in the state to get the value for.
             default_value (Optional[Any], optional): Default value to return
                 if the key is not found. Defaults to None.
+            timeout (Optional[float], optional): Seconds that each Redis
+                command of this call may take, e.g., 0.05 on a serving
+                path. Defaults to None, which uses the instance's
+                `redis_socket_timeout`.
+
+        Raises:
+            redis.exceptions.TimeoutError: If a Redis command took longer
+                than the timeout.
 
         Returns:
             Any: Current value for the key, or default_value if the key
             is not found.
         """
+        with self._executor._timeout(timeout):
+            self._executor._loadState()
+        value = self._executor._state.get(key, default_value)
+        return copy.deepcopy(value) if self._cache_returns == "copy" else value
+
+    def snapshot_view(self, keys: List[str]) -> Mapping[str, Any]:
+        """Gets the values for several keys in the component instance's
+        state, all from the same state version. Calling read_state once per
+        key could mix versions if another process writes in between.
+
+        Usage:
+        ```python
+        view = c_instance.snapshot_view(["a", "b"])
+        view["a"], view["b"] # Returns (0, 1)
+        ```
+
+        Args:
+            keys (List[str]): Keys in the state to get the values for.
+
+        Returns:
+            Mapping[str, Any]: Read-only mapping from each key to its value.
+            Keys that are not found are left out.
+        """
+        self._executor._loadState()
+        state = self._executor._state
+        return MappingProxyType(
+            {
+                key: copy.deepcopy(dict.__getitem__(state, key))
+                if self._cache_returns == "copy"
+                else dict.__getitem__(state, key)
+                for key in keys
+                if key in state
+            }
+        )
+
+    def sample(self, n: int, seed: Optional[int] = None) -> Dict[str, Any]:
+        """Gets n random key-value pairs from the component instance's
+        state, e.g., to spot-check values of a large instance.
+
+        Usage:
+        ```python
+        c_instance.sample(2, seed=0)
+        # Returns {"user_17": {...}, "user_3": {...}}
+        c_instance.sample(2, seed=0) # Returns the same pairs
+        ```
+
+        Args:
+            n (int): Number of pairs to get. If the state has fewer keys,
+                all of them are returned.
+            seed (Optional[int], optional): Seed for the random choice, so
+                that the same keys are picked each time. Defaults to None.
+
+        Raises:
+            ValueError: If n is negative.
+
+        Returns:
+            Dict[str, Any]: Mapping from each sampled key to its value.
+        """
+        if n < 0:
+            raise ValueError("n must not be negative.")
+
+        self._executor._loadState()
+        state = self._executor._state
+        keys = random.Random(seed).sample(list(state.keys()), min(n, len(state)))
+        return {
+            key: copy.deepcopy(dict.__getitem__(state, key))
+            if self._cache_returns == "copy"
+            else dict.__getitem__(state, key)
+            for key in keys
+        }
+
+    def pop_state(
+        self,
+        key: str,
+        default_value: Optional[Any] = None,
+        lock_policy: Optional[LockPolicy] = None,
+    ) -> Any:
+        """Removes the key from the component instance's state and returns
+        its value. The removal is done while holding the instance lock, so
+        the value is not lost to a concurrent update op.
+
+        Usage:
+        ```python
+        c_instance.pop_state("pending") # Returns [1, 2, 3]
+        c_instance.read_state("pending") # Returns None
+        c_instance.pop_state("pending", []) # Returns []
+        ```
+
+        Args:
+            key (str): Key in the state to remove.
+            default_value (Optional[Any], optional): Default value to return
+                if the key is not found. Defaults to None.
+            lock_policy (Optional[LockPolicy], optional): Defaults to waiting.
+
+        Raises:
+            redis.exceptions.LockError: If the lock could not be acquired
+                under the lock policy. The state is not changed.
+
+        Returns:
+            Any: Value that was removed, or default_value if the key
+            is not found. If the key is not found, the state version
+            is not bumped.
+        """
+        return self._executor._popState(key, default_value, lock_policy=lock_policy)
+
+    def rename_keys(
+        self, mapping: Dict[str, str], lock_policy: Optional[LockPolicy] = None
+    ) -> int:
+        """Renames keys in the component instance's state, e.g., during a
+        schema refactor. All keys are renamed together while holding the
+        instance lock, with a single version bump.
+
+        Usage:
+        ```python
+        c_instance.rename_keys({"modelA": "models/a", "modelB": "models/b"})
+        c_instance.read_state("models/a") # Returns the old modelA
+        ```
+
+        Args:
+            mapping (Dict[str, str]): Mapping from each key to rename to its
+                new name. Keys can be swapped, e.g., {"a": "b", "b": "a"}.
+            lock_policy (Optional[LockPolicy], optional): Defaults to waiting.
+
+        Raises:
+            KeyError: If a key to rename is not in the state.
+            ValueError: If a new name is already a key in the state (that
+                isn't also being renamed), or two keys would get the same
+                name. No keys are renamed.
+
+        Returns:
+            int: New version of the state.
+        """
+        return self._executor._renameKeys(mapping, lock_policy=lock_policy)
+
+    def apply(
+        self, ops: List[Tuple[Any, ...]], lock_policy: Optional[LockPolicy] = None
+    ) -> List[Any]:
+        """Applies a list of ops to the component instance's state as one
+        unit, e.g., "delete A, set B, increment C". The ops run in order
+        while holding the instance lock, and their changes are saved
+        together with a single version bump. If any op fails, none of the
+        changes are saved.
+
+        Usage:
+        ```python
+        c_instance.apply(
+            [("delete", "a"), ("set", "b", 2), ("incr", "count")]
+        ) # Returns [1, None, 1]
+        ```
+
+        Args:
+            ops (List[Tuple[Any, ...]]): Ops to apply, each one of
+                `("set", key, value)`, `("delete", key)`, or
+                `("incr", key, amount)`. The amount defaults to 1, and
+                incrementing a missing key starts from 0.
+            lock_policy (Optional[LockPolicy], optional): Defaults to waiting.
+
+        Raises:
+            ValueError: If an op is not one of the forms above.
+            ValueError: If the new state exceeds the component's limits.
+            TypeError: If an incr is applied to a value that isn't a number.
+
+        Returns:
+            List[Any]: Result of each op: None for a set, the removed value
+            (or None) for a delete, and the new value for an incr.
+        """
+        return self._executor._applyOps(ops, lock_policy=lock_policy)
+
+    def temp_keys(self) -> List[Tuple[str, float]]:
+        """Lists the Redis keys of this component instance that expire,
+        such as cached serve results and idempotency keys, with the number
+        of seconds until each one expires. State keys are not listed, since
+        the state is stored in one value that never expires.
+
+        Returns:
+            List[Tuple[str, float]]: Redis key and seconds left for each
+            expiring key, soonest first.
+        """
+        return self._executor._tempKeys()
+
+    def init_keys(
+        self, defaults: Dict[str, Any], lock_policy: Optional[LockPolicy] = None
+    ) -> Dict[str, Any]:
+        """Sets each key that isn't in the component instance's state yet to
+        its default, and returns the current values of all the keys. Keys
+        that already exist keep their values. The check and the write happen
+        under the instance lock, so concurrent callers never overwrite each
+        other's values, and the version only changes if a key was missing.
+
+        Args:
+            defaults (Dict[str, Any]): Default value for each key.
+            lock_policy (Optional[LockPolicy], optional): Defaults to waiting.
+
+        Raises:
+            SchemaError: If the component has a schema and a default does
+                not match it. Nothing is written.
+
+        Returns:
+            Dict[str, Any]: Current value of each key in defaults.
+        """
+        return self._executor._initKeys(defaults, lock_policy=lock_policy)
+
+    def patch(
+        self,
+        key: str,
+        json_patch: List[Dict[str, Any]],
+        lock_policy: Optional[LockPolicy] = None,
+    ) -> Tuple[Any, int]:
+        """Applies an RFC 6902 JSON patch to a dict (or list) value in the
+        component instance's state, e.g., a config edited in a front end.
+        The value is patched and written back while holding the instance
+        lock, so concurrent patches don't overwrite each other.
+
+        Usage:
+        ```python
+        c_instance.patch(
+            "config",
+            [
+                {"op": "replace", "path": "/threshold", "value": 0.8},
+                {"op": "add", "path": "/tags/-", "value": "b"},
+            ],
+        ) # Returns ({"threshold": 0.8, "tags": ["a", "b"]}, 2)
+        ```
+
+        Args:
+            key (str): Key of the value in the state to patch.
+            json_patch (List[Dict[str, Any]]): Patch ops, e.g.,
+                `{"op": "add", "path": "/a", "value": 1}`. Supports the
+                add, remove, replace, move, copy, and test ops.
+            lock_policy (Optional[LockPolicy], optional): Defaults to waiting.
+
+        Raises:
+            KeyError: If the key is not in the state.
+            JsonPatchError: If an op is malformed, refers to a path that
+                doesn't exist, or is a test that fails. None of the ops are
+                applied.
+
+        Returns:
+            Tuple[Any, int]: The patched value and the new state version.
+        """
+        return self._executor._patchValue(key, json_patch, lock_policy=lock_policy)
+
+    def get_and_update(
+        self,
+        key: str,
+        func: Callable[[Any], Any],
+        default_value: Optional[Any] = None,
+        timeout: float = 60,
+        lock_policy: Optional[LockPolicy] = None,
+    ) -> Any:
+        """Atomically reads the key, transforms its value with func, and
+        writes the result back, bumping the version. The instance lock is
+        held across the call to func, so no other write can happen in
+        between.
+
+        Usage:
+        ```python
+        c_instance.get_and_update("count", lambda c: c + 1) # Returns 1
+        c_instance.get_and_update("seen", lambda s: s + ["a"])
+        # Returns ["a"]
+        ```
+
+        Args:
+            key (str): Key in the state to update.
+            func (Callable[[Any], Any]): Function that takes the current
+                value and returns the new value. Should be quick, since
+                the lock is held while it runs.
+            default_value (Optional[Any], optional): Value to pass to func
+                if the key is not found. Defaults to None.
+            timeout (float, optional): Maximum number of seconds func may
+                run for. Defaults to 60, which is below the lock's 120
+                second expiry. After a timeout, func keeps running in the
+                background. Anything it changes in place from then on is
+                dropped, because the state is reloaded from Redis.
+            lock_policy (Optional[LockPolicy], optional): Defaults to waiting.
+
+        Raises:
+            TimeoutError: If func does not return within the timeout. The
+                state is not changed.
+            redis.exceptions.LockError: If the lock could not be acquired
+                under the lock policy.
+
+        Returns:
+            Any: New value of the key.
+        """
+        return self._executor._getAndUpdate(
+            key, func, default_value, timeout=timeout, lock_policy=lock_policy
+        )
+
+    def digest(self) -> Optional[str]:
+        """Gets a hash of the component instance's state as stored in
+        Redis. The hash is computed by Redis, so the state is not fetched.
+        Useful for pollers that only want to reload the state when
+        something has changed.
+
+        Usage:
+        ```python
+        c_instance = C()
+        digest = c_instance.digest()
+        ...
+        if c_instance.digest() != digest:
+            # Something changed, so read the state again
+            c_instance.read_state("value")
+        ```
+
+        Returns:
+            Optional[str]: SHA1 hex digest of the serialized state, or None
+            if the state does not exist.
+        """
+        return self._executor._digest()
+
+    def verify(self, heal: bool = False) -> Dict[str, Any]:
+        """Checks this handle's cached copy of the state against the state
+        in Redis, e.g., to track down suspected drift. A mismatch at the
+        same version usually means a value returned with
+        `cache_returns="live"` was mutated in place.
+
+        Usage:
+        ```python
+        c_instance.read_state("items").append(1) # Mutates the cache
+        c_instance.verify()
+        # Returns {"cached_version": 1, "stored_version": 1,
+        # "mismatched_keys": ["items"]}
+        c_instance.verify(heal=True) # Reloads the state
+        ```
+
+        Args:
+            heal (bool, optional): Whether to drop the cached copy and
+                reload the state from Redis if it is stale or mismatched.
+                Defaults to False.
+
+        Returns:
+            Dict[str, Any]: Report with the `cached_version` of this
+            handle's copy (None if nothing is cached), the
+            `stored_version` in Redis, and the `mismatched_keys` whose
+            cached and stored values serialize differently or that are
+            only in one of them. Keys with buffered writes from
+            `write_coalesce_ms` are skipped. If the versions differ, the
+            cached copy is just stale and mismatches are expected.
+        """
+        return self._executor._verify(heal)
+
+    def get_raw_state(self) -> bytes:
+        """Gets the component instance's state as the bytes stored in
+        Redis, without deserializing it. Useful for backups or moving
+        state between systems. The state is stored as one value, so this
+        returns the whole state rather than a single key.
+
+        Usage:
+        ```python
+        raw_state = C("source").get_raw_state()
+        C("backup").set_raw_state(raw_state)
+        ```
+
+        Raises:
+            ValueError: If the instance's state does not exist.
+
+        Returns:
+            bytes: Serialized state, as written by save_state and
+            cloudpickle.
+        """
+        return self._executor._getRawState()
+
+    def set_raw_state(
+        self, raw_state: bytes, lock_policy: Optional[LockPolicy] = None
+    ) -> int:
+        """Replaces the component instance's state with bytes from
+        `get_raw_state`, e.g., to repair a state that can't be decoded. The
+        bytes are stored as is, but are deserialized first to check them
+        against the component's schema and limits. The write is done while
+        holding the instance lock and bumps the version, and per-key
+        metadata, history, and change events are updated like for
+        `write_state`.
+
+        Usage:
+        ```python
+        c_instance.set_raw_state(backup.get_raw_state()) # Returns the
+        # new version
+        ```
+
+        Args:
+            raw_state (bytes): Serialized state from `get_raw_state`.
+            lock_policy (Optional[LockPolicy], optional): Defaults to waiting.
+
+        Raises:
+            TypeError: If raw_state is not bytes.
+            SchemaError: If the component has a schema and the state does
+                not match it.
+            ValueError: If raw_state can't be deserialized, or exceeds the
+                component's limits.
+
+        Returns:
+            int: New version of the state.
+        """
+        return self._executor._setRawState(raw_state, lock_policy=lock_policy)
+
+    def last_modified(self, key: str) -> Optional[float]:
+        """Gets the time the key was last written to in the component
+        instance's state. Useful for debugging stale state.
+
+        Usage:
+        ```python
+        c_instance.last_modified("value") # Returns the time setUp ran
+        c_instance.write_state({"value": 1})
+        c_instance.last_modified("value") # Returns the time of the write
+        ```
+
+        Args:
+            key (str): Key in the state to get the last-modified time for.
+
+        Returns:
+            Optional[float]: Unix timestamp of the last write to the key,
+            or None if the key is not in the state or was written before
+            per-key metadata was recorded.
+        """
+        meta = self._executor._loadKeyMeta([key]).get(key, {})
+        return meta.get("modified_at")
+
+    def last_writer(self, key: str) -> Optional[str]:
+        """Gets the writer id of the component instance that last wrote
+        to the key. Useful for figuring out which process or host
+        is responsible for a bad value in the state.
+
+        Usage:
+        ```python
+        c_instance.write_state({"value": 1})
+        c_instance.last_writer("value") # Returns "worker-1"
+        ```
+
+        Args:
+            key (str): Key in the state to get the last writer for.
+
+        Returns:
+            Optional[str]: Writer id of the last write to the key, or None
+            if the key is not in the state or was written without one.
+        """
+        meta = self._executor._loadKeyMeta([key]).get(key, {})
+        return meta.get("writer")
+
+    def get_matching(
+        self,
+        prefix: str = "",
+        types: Optional[Union[type, Tuple[type, ...]]] = None,
+    ) -> Dict[str, Any]:
+        """Gets the keys in the component instance's state that start with
+        the prefix and, optionally, whose values are of the given types.
+
+        Usage:
+        ```python
+        c_instance.get_matching("stats/", (int, float))
+        # Returns {"stats/count": 10, "stats/mean": 0.5}
+        ```
+
+        Args:
+            prefix (str, optional): Prefix the keys must start with.
+                Defaults to "", which matches every key.
+            types (Optional[Union[type, Tuple[type, ...]]], optional): Type or
+                tuple of types the values must be instances of, as in
+                `isinstance`. Defaults to None, which matches any value.
+
+        Returns:
+            Dict[str, Any]: Mapping from each matching key to its value.
+        """
+        self._executor._loadState()
+        return {
+            key: copy.deepcopy(value) if self._cache_returns == "copy" else value
+            for key, value in self._executor._state.items()
+            if isinstance(key, str)
+            and key.startswith(prefix)
+            and (types is None or isinstance(value, types))
+        }
+
+    def items_with_meta(self) -> Dict[str, Dict[str, Any]]:
+        """Gets every key in the component instance's state along with its
+        value and the metadata recorded on its last write.
+
+        Usage:
+        ```python
+        c_instance.items_with_meta()
+        # Returns {"value": {"value": 0, "modified_at": ..., ...}}
+        ```
+
+        Returns:
+            Dict[str, Dict[str, Any]]: Mapping from each key in the state to
+            a dict with the key's `value`, the `modified_at` Unix timestamp of
+            its last write, the state `version` that write produced, and the
+            `writer` id of the instance that made it. The metadata fields are
+            None for keys written before per-key metadata was recorded.
+        """
         self._executor._loadState()
-        return self._executor._state.get(key, default_value)
+        meta = self._executor._loadKeyMeta()
+        return {
+            key: {
+                "value": copy.deepcopy(value)
+                if self._cache_returns == "copy"
+                else value,
+                "modified_at": meta.get(key, {}).get("modified_at"),
+                "version": meta.get(key, {}).get("version"),
+                "writer": meta.get(key, {}).get("writer"),
+            }
+            for key, value in self._executor._state.items()
+        }
+
+    def items_since(self, version: int) -> Tuple[Dict[str, Any], int]:
+        """Gets the keys in the component instance's state that were written
+        after the given state version, along with the current version. This
+        lets a poller sync incrementally by passing back the version it got
+        from the previous call.
+
+        Usage:
+        ```python
+        changed, version = c_instance.items_since(0)
+        # Returns ({"value": 0}, 1)
+        c_instance.write_state({"value": 1})
+        changed, version = c_instance.items_since(version)
+        # Returns ({"value": 1}, 2)
+        ```
+
+        Args:
+            version (int): State version the caller last saw.
+
+        Returns:
+            Tuple[Dict[str, Any], int]: Mapping from each key written after
+            `version` to its current value, and the current state version.
+            Keys written before per-key metadata was recorded are always
+            included. Deleted keys are not reported; use `subscribe` to be
+            notified of deletions.
+        """
+        self._executor._loadState()
+        current_version = self._executor.version
+        meta = self._executor._loadKeyMeta()
+        changed = {}
+        for key, value in self._executor._state.items():
+            key_version = meta.get(key, {}).get("version")
+            if key_version is None or key_version > version:
+                changed[key] = (
+                    copy.deepcopy(value) if self._cache_returns == "copy" else value
+                )
+
+        return changed, current_version  # type: ignore
+
+    def transfer_keys(
+        self,
+        dest_instance_id: str,
+        keys: List[str],
+        lock_policy: Optional[LockPolicy] = None,
+    ) -> None:
+        """Moves keys from this component instance's state to the state of
+        another instance of the same component, e.g., when splitting or
+        re-sharding instances. Both instance locks are held while the keys
+        are moved, and both states are written in one transaction, so the
+        keys are never missing from (or in) both instances.
+
+        Usage:
+        ```python
+        a.write_state({"user_1": ..., "user_2": ...})
+        a.transfer_keys("shard_b", ["user_2"])
+        a.read_state("user_2") # Returns None
+        b.read_state("user_2") # Returns the value from shard_a
+        ```
+
+        Args:
+            dest_instance_id (str): Id of the instance to move the keys to.
+                It must already exist.
+            keys (List[str]): Keys in the state to move.
+            lock_policy (Optional[LockPolicy], optional): Defaults to waiting.
+
+        Raises:
+            KeyError: If any of the keys are not in this instance's state.
+            ValueError: If the destination instance does not exist or either
+                state would violate the component's limits or schema.
+            redis.exceptions.LockError: If either lock could not be acquired
+                under the lock policy. Nothing is moved.
+        """
+        self._executor._transferKeys(dest_instance_id, keys, lock_policy=lock_policy)
+
+    def get_at_version(
+        self, key: str, version: int, default_value: Optional[Any] = None
+    ) -> Any:
+        """Gets the value the key had at a past version of the component
+        instance's state. Useful for debugging bad update ops. Requires the
+        component to be created with `history_size`, which is the number
+        of past values kept for each key.
+
+        Usage:
+        ```python
+        c_instance.write_state({"value": 1})
+        c_instance.get_version() # Returns 2
+        c_instance.get_at_version("value", 1) # Returns 0
+        c_instance.get_at_version("value", 2) # Returns 1
+        ```
+
+        Args:
+            key (str): Key in the state to get the value for.
+            version (int): Version of the state to read the key at.
+            default_value (Optional[Any], optional): Default value to return
+                if the key did not exist at that version. Defaults to None.
+
+        Raises:
+            ValueError: If history is not kept for the component, the version
+                does not exist, or the key's value at that version is older
+                than the history kept.
+
+        Returns:
+            Any: Value of the key at the version, or default_value if the key
+            did not exist at that version.
+        """
+        return self._executor._getAtVersion(key, version, default_value)
+
+    def rollback(self, version: int, lock_policy: Optional[LockPolicy] = None) -> int:
+        """Restores every key in the component instance's state to the value
+        it had at a past version, e.g., to undo a bad update op. The rollback
+        is done while holding the instance lock and is saved as a new version
+        of the state, so it shows up in `get_at_version`, `last_modified`,
+        and `last_writer` like any other write. Requires the component to be
+        created with `history_size`.
+
+        Usage:
+        ```python
+        c_instance.write_state({"value": 1, "bad": True})
+        c_instance.rollback(1) # Returns 3
+        c_instance.read_state("value") # Returns 0
+        c_instance.read_state("bad") # Returns None
+        ```
+
+        Args:
+            version (int): Version of the state to restore.
+            lock_policy (Optional[LockPolicy], optional): Defaults to waiting.
+
+        Raises:
+            ValueError: If history is not kept for the component, the version
+                does not exist, or a key's value at that version is older
+                than the history kept.
+            redis.exceptions.LockError: If the lock could not be acquired
+                under the lock policy. The state is not changed.
+
+        Returns:
+            int: Version of the state after the rollback.
+        """
+        return self._executor._rollback(version, lock_policy=lock_policy)
+
+    def subscribe(self, key_pattern: str, callback: Callable) -> None:
+        """Calls the callback whenever a key in the component instance's
+        state that matches the pattern is written or deleted, by any handle
+        to this instance (including update ops). Callbacks are called from
+        a background thread until the instance is shut down.
+
+        Usage:
+        ```python
+        c_instance.subscribe("model*", lambda event: print(event["key"]))
+        c_instance.run(...) # Prints "model" and "model_version" if the
+        # update op writes them
+        ```
+
+        Args:
+            key_pattern (str): Glob-style pattern (e.g., "model*") of the
+                keys to be notified about.
+            callback (Callable): Function that takes an event dict with the
+                changed `key`, the state `version` of the write, the `writer`
+                id of the instance that made it, and whether the key was
+                `deleted`.
+        """
+        self._executor._subscribe(key_pattern, callback)
+
+    def access_report(self) -> Dict[str, int]:
+        """Gets the number of times each key in the component instance's
+        state has been read, across all handles to this instance. Useful
+        for deciding which keys to cache or precompute. Requires the
+        component to be created with `track_access=True`.
+
+        Usage:
+        ```python
+        c_instance.run("predict", props={"x": ...})
+        c_instance.access_report() # Returns {"model": 1}
+        ```
+
+        Returns:
+            Dict[str, int]: Mapping from each key that has been read to its
+            read count, sorted from most to least read. Empty if access
+            tracking is off.
+        """
+        return self._executor._accessReport()
+
+    def last_op_info(self) -> Optional[Dict[str, Any]]:
+        """Gets how the state was serialized or deserialized in the most
+        recent save or load done by this handle to the component instance.
+        Useful for finding out which writes are expensive to serialize.
+
+        Usage:
+        ```python
+        c_instance = C()
+        c_instance.write_state({"embeddings": ...})
+        c_instance.last_op_info()
+        # Returns {"op": "save", "serializer": "cloudpickle",
+        # "bytes": 1048576, "duration": 0.012, "keys": ["embeddings"]}
+        ```
+
+        Returns:
+            Optional[Dict[str, Any]]: The operation ("load" or "save"), the
+            serializer used, the size of the serialized state in bytes, the
+            time it took to (de)serialize in seconds, and the keys that were
+            written or loaded. None if no state has been saved or loaded
+            yet.
+        """
+        return self._executor._last_op_info
+
+    def metrics(self) -> Dict[str, Any]:
+        """Gets aggregate serialization counters for the saves and loads
+        done by this handle to the component instance. Updates run by
+        the update task are not included.
+
+        Usage:
+        ```python
+        c_instance = C()
+        c_instance.write_state({"value": 1})
+        c_instance.metrics()
+        # Returns {"load_count": 0, "load_bytes": 0, "load_seconds": 0,
+        # "save_count": 2, "save_bytes": ..., "save_seconds": ...}
+        ```
+
+        Returns:
+            Dict[str, Any]: Number of loads and saves, total bytes
+            serialized or deserialized, and total seconds spent doing so.
+        """
+        return self._executor._metrics()
+
+    def flush_writes(self) -> Optional[int]:
+        """Writes any `write_state` updates that are buffered because the
+        instance was created with `write_coalesce_ms`. Buffered writes are
+        also flushed when the coalescing window ends and on shutdown.
+
+        Usage:
+        ```python
+        c_instance = C(write_coalesce_ms=50)
+        for i in range(1000):
+            c_instance.write_state({"progress": i}) # Buffered
+        c_instance.flush_writes() # Writes {"progress": 999} once
+        ```
+
+        Raises:
+            redis.exceptions.LockError: If the instance lock could not be
+                acquired.
+
+        Returns:
+            Optional[int]: Version of the state produced by the flush, or
+            None if there were no buffered writes.
+        """
+        return self._executor._flushWrites()
+
+    def flush_and_wait(self, replicas: int = 0, timeout: float = 1) -> int:
+        """Writes any buffered `write_state` updates and, if replicas is
+        positive, waits until that many Redis replicas have acknowledged
+        this instance's writes. Useful after writing a checkpoint, before
+        moving on.
+
+        Usage:
+        ```python
+        c_instance = C(write_coalesce_ms=50)
+        c_instance.write_state({"checkpoint": ...})
+        c_instance.flush_and_wait(replicas=1, timeout=5) # Returns the
+        # version that is now on the primary and one replica
+        ```
+
+        Args:
+            replicas (int, optional): Number of replicas that must
+                acknowledge the writes. Defaults to 0, which only flushes.
+            timeout (float, optional): Maximum number of seconds to wait for
+                the replicas. Defaults to 1.
+
+        Raises:
+            TimeoutError: If fewer than `replicas` replicas acknowledged the
+                writes within the timeout. The writes are still on the
+                primary.
+
+        Returns:
+            int: Version of the state that was confirmed.
+        """
+        return self._executor._flushAndWait(replicas, timeout)
 
     def flush_update(self, flow_key: str) -> None:
         """Flushes the update queue corresponding to the flow
@@ -355,6 +1268,125 @@ class ComponentInstance:
 
         self._executor.flush_update(flow_key)
 
+    def peek_updates(self, flow_key: str, n: int = 10) -> List[Dict[str, Any]]:
+        """Gets the update ops waiting in the update queues for the flow key,
+        without removing them, e.g., to debug a backed-up queue.
+
+        Usage:
+        ```python
+        c.run("add", props={"value": 1})
+        c.peek_updates("add")
+        # Returns [{"identifier": ..., "udf_name": "add",
+        # "props": {"value": 1}, "expire_at": None,
+        # "high_priority": False}] if the update op hasn't
+        # started yet
+        ```
+
+        Args:
+            flow_key (str): Key of the flow.
+            n (int, optional): Maximum number of queued update ops to return
+                per update op of the flow and priority. Defaults to 10.
+
+        Returns:
+            List[Dict[str, Any]]: Queued update ops for each update op of
+            the flow, high priority first and then oldest first. Each has
+            the `identifier` to pass to `remove_update`, the `udf_name` of
+            the update op, the `props` it will run with, the `expire_at`
+            Unix timestamp after which it is discarded (or None), and
+            whether it is `high_priority`. Empty if the flow has no update
+            ops.
+        """
+        return [item for _, _, item in self._executor._queuedUpdates(flow_key, n)]
+
+    def find_updates(
+        self, flow_key: str, predicate: Callable[[Dict[str, Any]], bool]
+    ) -> List[Dict[str, Any]]:
+        """Gets the update ops waiting in the update queues for the flow key
+        that match a predicate, without removing them.
+
+        Usage:
+        ```python
+        c.run("add", props={"value": -1})
+        bad = c.find_updates("add", lambda u: u["props"]["value"] < 0)
+        for update in bad:
+            c.remove_update("add", update["identifier"])
+        ```
+
+        Args:
+            flow_key (str): Key of the flow.
+            predicate (Callable[[Dict[str, Any]], bool]): Called with each
+                queued update op, in the format returned by `peek_updates`.
+
+        Returns:
+            List[Dict[str, Any]]: Queued update ops that match the predicate.
+        """
+        return [
+            item
+            for _, _, item in self._executor._queuedUpdates(flow_key)
+            if predicate(item)
+        ]
+
+    def remove_update(self, flow_key: str, identifier: str) -> bool:
+        """Removes an update op from the update queues for the flow key, so
+        that it never runs. Other queued update ops are left in place.
+
+        Usage:
+        ```python
+        c.run("add", props={"value": 1})
+        for update in c.peek_updates("add"):
+            c.remove_update("add", update["identifier"])
+        ```
+
+        Args:
+            flow_key (str): Key of the flow.
+            identifier (str): Identifier of the queued update op, from
+                `peek_updates` or `find_updates`.
+
+        Returns:
+            bool: Whether the update op was removed. False if it isn't in
+            the queue, e.g., because the update task already started it.
+        """
+        return self._executor._removeQueuedUpdate(flow_key, identifier)
+
+    def drain(self, timeout: Optional[float] = None) -> bool:
+        """Waits until the update task has finished every queued and
+        in-flight update op of this instance, e.g., before a deploy shuts
+        the instance down. Update ops left in flight by dead update tasks
+        are requeued first (see `requeue_inflight`).
+
+        Usage:
+        ```python
+        c.run("add", props={"value": 1})
+        c.drain(timeout=30) # Returns True once the update op is done
+        ```
+
+        Args:
+            timeout (Optional[float], optional): Seconds to wait. Defaults
+                to None, which waits until the queues are empty. If the
+                instance was created with `disable_update_task=True`, only
+                another handle's update task empties them.
+
+        Returns:
+            bool: Whether the queues were drained before the timeout.
+        """
+        return self._executor._drain(timeout)
+
+    def requeue_inflight(self) -> int:
+        """Moves update ops that dead update tasks popped but didn't finish
+        back to the front of their queues, so the next update task runs
+        them. Running update tasks do this every 30 seconds; call it to
+        recover right away, e.g., after a worker crashed.
+
+        Usage:
+        ```python
+        c.requeue_inflight() # Returns the number of update ops requeued
+        ```
+
+        Returns:
+            int: Number of update ops requeued.
+        """
+        return self._executor._requeueInflight()
+
     def gen(
         self,
         flow_key: str,
@@ -362,6 +1394,8 @@ class ComponentInstance:
         ignore_cache: bool = False,
         force_refresh: bool = False,
         flush_update: bool = False,
+        high_priority: bool = False,
+        idempotency_key: Optional[str] = None,
     ) -> Generator[Any, None, None]:
         """Runs the flow (serve and update ops) for the specified key and
         yields the results as they come in, as a generator. Use this if your
@@ -408,11 +1442,18 @@ class ComponentInstance:
                 returning. If the update queue hasn't reached batch_size
                 yet, the update op runs anyways. Force refreshes the
                 state after the update op completes. Defaults to False.
+            high_priority (bool, optional): Queues the update ops ahead of
+                others. Defaults to False.
+            idempotency_key (Optional[str], optional): Skips the update ops
+                if a run in the last day used the same key, and raises
+                DuplicateRunError. Defaults to None.
 
         Raises:
             ValueError: If more than one flow key-value pair is passed.
                 If flush_update is called and the component instance update
                 processes are disabled.
+            DuplicateRunError: If idempotency_key was already used. Its
+                `serve_result` has the result of the serve op.
 
         Returns:
             Awaitable[Any]: Awaitable Result of the serve call.
@@ -423,6 +1464,8 @@ class ComponentInstance:
             ignore_cache=ignore_cache,
             force_refresh=force_refresh,
             flush_update=flush_update,
+            high_priority=high_priority,
+            idempotency_key=idempotency_key,
         ):  # type: ignore
             yield elem
 
@@ -436,6 +1479,8 @@ class ComponentInstance:
         ignore_cache: bool = False,
         force_refresh: bool = False,
         flush_update: bool = False,
+        high_priority: bool = False,
+        idempotency_key: Optional[str] = None,
     ) -> Any:
         """Runs the flow (serve and update ops) for the keyword argument
         passed in. If the key is not found to have any ops, an error
@@ -493,12 +1538,19 @@ class ComponentInstance:
                 returning. If the update queue hasn't reached batch_size
                 yet, the update op runs anyways. Force refreshes the
                 state after the update op completes. Defaults to False.
+            high_priority (bool, optional): Queues the update ops ahead of
+                others. Defaults to False.
+            idempotency_key (Optional[str], optional): Skips the update ops
+                if a run in the last day used the same key, and raises
+                DuplicateRunError. Defaults to None.
 
          Raises:
             ValueError: If more than one flow key-value pair is passed.
             RuntimeError:
                 If flush_update is called and the component instance update
                 processes are disabled.
+            DuplicateRunError: If idempotency_key was already used. Its
+                `serve_result` has the result of the serve op.
 
         Returns:
             Any: Result of the serve call. Might take a long time
@@ -508,7 +1560,13 @@ class ComponentInstance:
 
         serve_result = []
         for elem in self.gen(
-            flow_key, props, ignore_cache, force_refresh, flush_update
+            flow_key,
+            props,
+            ignore_cache,
+            force_refresh,
+            flush_update,
+            high_priority,
+            idempotency_key,
         ):
             serve_result.append(elem)
 
@@ -521,6 +1579,8 @@ class ComponentInstance:
         ignore_cache: bool = False,
         force_refresh: bool = False,
         flush_update: bool = False,
+        high_priority: bool = False,
+        idempotency_key: Optional[str] = None,
     ) -> AsyncGenerator[Any, None]:
         """Async version of gen. Runs the flow (serve and update ops) for
         the specified key and yields the results as they come in,
@@ -566,11 +1626,18 @@ class ComponentInstance:
                 returning. If the update queue hasn't reached batch_size
                 yet, the update op runs anyways. Force refreshes the
                 state after the update op completes. Defaults to False.
+            high_priority (bool, optional): Queues the update ops ahead of
+                others. Defaults to False.
+            idempotency_key (Optional[str], optional): Skips the update ops
+                if a run in the last day used the same key, and raises
+                DuplicateRunError. Defaults to None.
 
         Raises:
             ValueError: If more than one flow key-value pair is passed.
                 If flush_update is called and the component instance update
                 processes are disabled.
+            DuplicateRunError: If idempotency_key was already used. Its
+                `serve_result` has the result of the serve op.
 
         Returns:
             Awaitable[Any]: Awaitable Result of the serve call.
@@ -581,6 +1648,8 @@ class ComponentInstance:
             ignore_cache=ignore_cache,
             force_refresh=force_refresh,
             flush_update=flush_update,
+            high_priority=high_priority,
+            idempotency_key=idempotency_key,
         ):  # type: ignore
             yield elem
 
@@ -594,6 +1663,8 @@ class ComponentInstance:
         ignore_cache: bool = False,
         force_refresh: bool = False,
         flush_update: bool = False,
+        high_priority: bool = False,
+        idempotency_key: Optional[str] = None,
     ) -> Awaitable[Any]:
         """Async version of run. Runs the flow (serve and update ops) for
         the specified key. You should use arun if either the serve or update op
@@ -635,11 +1706,18 @@ class ComponentInstance:
                 returning. If the update queue hasn't reached batch_size
                 yet, the update op runs anyways. Force refreshes the
                 state after the update op completes. Defaults to False.
+            high_priority (bool, optional): Queues the update ops ahead of
+                others. Defaults to False.
+            idempotency_key (Optional[str], optional): Skips the update ops
+                if a run in the last day used the same key, and raises
+                DuplicateRunError. Defaults to None.
 
         Raises:
             ValueError: If more than one flow key-value pair is passed.
                 If flush_update is called and the component instance update
                 processes are disabled.
+            DuplicateRunError: If idempotency_key was already used. Its
+                `serve_result` has the result of the serve op.
 
         Returns:
             Awaitable[Any]: Awaitable Result of the serve call.
@@ -649,7 +1727,13 @@ class ComponentInstance:
         results = []
 
         async for elem in self.agen(
-            flow_key, props, ignore_cache, force_refresh, flush_update
+            flow_key,
+            props,
+            ignore_cache,
+            force_refresh,
+            flush_update,
+            high_priority,
+            idempotency_key,
         ):
             results.append(elem)
 
@@ -0,0 +1,120 @@
+import copy
+from typing import Any, Dict, List
+
+
+class JsonPatchError(ValueError):
+    """Raised when a JSON patch op is malformed or can't be applied."""
+
+    def __init__(self, op: Dict[str, Any], message: str):
+        self.op = op
+        super().__init__(f"Could not apply JSON patch op {op!r}: {message}")
+
+
+def _parsePointer(op: Dict[str, Any], pointer: Any) -> List[str]:
+    if not isinstance(pointer, str) or (pointer and not pointer.startswith("/")):
+        raise JsonPatchError(op, f"{pointer!r} is not a JSON pointer.")
+
+    if not pointer:
+        return []
+    return [
+        token.replace("~1", "/").replace("~0", "~")
+        for token in pointer[1:].split("/")
+    ]
+
+
+def _listIndex(
+    op: Dict[str, Any], container: List[Any], token: str, inserting: bool
+) -> int:
+    if inserting and token == "-":
+        return len(container)
+
+    if not token.isdigit() or (token != "0" and token.startswith("0")):
+        raise JsonPatchError(op, f"{token!r} is not a list index.")
+
+    index = int(token)
+    if index > len(container) or (index == len(container) and not inserting):
+        raise JsonPatchError(op, f"Index {index} is out of range.")
+    return index
+
+
+def _get(op: Dict[str, Any], document: Any, tokens: List[str]) -> Any:
+    for token in tokens:
+        if isinstance(document, list):
+            document = document[_listIndex(op, document, token, False)]
+        elif isinstance(document, dict) and token in document:
+            document = document[token]
+        else:
+            raise JsonPatchError(op, f"Path does not exist at {token!r}.")
+
+    return document
+
+
+def _add(op: Dict[str, Any], document: Any, tokens: List[str], value: Any) -> Any:
+    if not tokens:
+        return value
+
+    parent = _get(op, document, tokens[:-1])
+    if isinstance(parent, list):
+        parent.insert(_listIndex(op, parent, tokens[-1], True), value)
+    elif isinstance(parent, dict):
+        parent[tokens[-1]] = value
+    else:
+        raise JsonPatchError(op, "Parent of the path is not a dict or list.")
+
+    return document
+
+
+def _remove(op: Dict[str, Any], document: Any, tokens: List[str]) -> Any:
+    if not tokens:
+        raise JsonPatchError(op, "Can't remove the whole value.")
+
+    parent = _get(op, document, tokens[:-1])
+    if isinstance(parent, list):
+        return parent.pop(_listIndex(op, parent, tokens[-1], False))
+    elif isinstance(parent, dict) and tokens[-1] in parent:
+        return parent.pop(tokens[-1])
+
+    raise JsonPatchError(op, "Path does not exist.")
+
+
+def apply_json_patch(document: Any, patch: List[Dict[str, Any]]) -> Any:
+    """Applies an RFC 6902 JSON patch to a dict (or list) and returns the
+    result. The document passed in is not modified, and if any op fails,
+    a JsonPatchError is raised and none of the ops are applied."""
+    document = copy.deepcopy(document)
+
+    for op in patch:
+        if not isinstance(op, dict) or "path" not in op:
+            raise JsonPatchError(op, "Ops need an 'op' and a 'path'.")
+
+        name = op.get("op")
+        tokens = _parsePointer(op, op["path"])
+        if name in ["add", "replace", "test"] and "value" not in op:
+            raise JsonPatchError(op, f"'{name}' ops need a 'value'.")
+        if name in ["move", "copy"] and "from" not in op:
+            raise JsonPatchError(op, f"'{name}' ops need a 'from'.")
+
+        if name == "add":
+            document = _add(op, document, tokens, copy.deepcopy(op["value"]))
+        elif name == "remove":
+            _remove(op, document, tokens)
+        elif name == "replace":
+            if tokens:
+                _remove(op, document, tokens)
+            document = _add(op, document, tokens, copy.deepcopy(op["value"]))
+        elif name == "move":
+            from_tokens = _parsePointer(op, op["from"])
+            if tokens[: len(from_tokens)] == from_tokens and tokens != from_tokens:
+                raise JsonPatchError(op, "Can't move a value into itself.")
+            value = _remove(op, document, from_tokens)
+            document = _add(op, document, tokens, value)
+        elif name == "copy":
+            value = _get(op, document, _parsePointer(op, op["from"]))
+            document = _add(op, document, tokens, copy.deepcopy(value))
+        elif name == "test":
+            if _get(op, document, tokens) != op["value"]:
+                raise JsonPatchError(op, "Test failed.")
+        else:
+            raise JsonPatchError(op, f"Unknown op {name!r}.")
+
+    return document
@@ -10,7 +10,13 @@ from tqdm import tqdm
 
 from motion.component import Component
 from motion.dicts import State
-from motion.utils import get_redis_params, loadState, saveState
+from motion.utils import (
+    KeyScheme,
+    RedisParams,
+    get_redis_params,
+    loadState,
+    saveState,
+)
 
 logger = logging.getLogger(__name__)
 
@@ -20,13 +26,17 @@ def process_migration(
     migrate_func: Callable,
     load_state_fn: Callable,
     save_state_fn: Callable,
+    key_scheme: KeyScheme,
+    redis_params: Optional[RedisParams] = None,
 ) -> Tuple[str, Optional[Exception]]:
     try:
-        rp = get_redis_params()
+        rp = redis_params or get_redis_params()
         redis_con = redis.Redis(
             **rp.dict(),
         )
-        state, version = loadState(redis_con, instance_name, load_state_fn)
+        state, version = loadState(
+            redis_con, instance_name, load_state_fn, key_scheme=key_scheme
+        )
 
         new_state = migrate_func(state)
         assert isinstance(new_state, dict), (
@@ -40,7 +50,14 @@ def process_migration(
         )
         empty_state.update(new_state)
         success_indicator = saveState(
-            empty_state, version, redis_con, instance_name, save_state_fn
+            empty_state,
+            version,
+            redis_con,
+            instance_name,
+            save_state_fn,
+            updated_keys=empty_state.keys(),
+            deleted_keys=set(state.keys()) - set(empty_state.keys()),
+            key_scheme=key_scheme,
         )
 
         if success_indicator == -1:
@@ -126,7 +143,7 @@ class StateMigrator:
 
         # Read all the states
 
-        rp = get_redis_params()
+        rp = get_redis_params(self.component._redis_params)
         redis_con = redis.Redis(
             **rp.dict(),
         )
@@ -135,9 +152,10 @@ class StateMigrator:
             for iid in instance_ids
         ]
         if not instance_names:
+            state_prefix = f"{self.component._key_scheme.state}:"
             instance_names = [
-                key.decode("utf-8").replace("MOTION_STATE:", "")  # type: ignore
-                for key in redis_con.keys(f"MOTION_STATE:{self.component.name}__*")
+                key.decode("utf-8").replace(state_prefix, "")  # type: ignore
+                for key in redis_con.keys(f"{state_prefix}{self.component.name}__*")
             ]
 
         if not instance_names:
@@ -152,6 +170,8 @@ class StateMigrator:
                     self.migrate_func,
                     self.component._load_state_func,
                     self.component._save_state_func,
+                    self.component._key_scheme,
+                    rp,
                 )
                 for instance_name in instance_names
             ]
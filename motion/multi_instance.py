@@ -0,0 +1,318 @@
+"""
+This file has utilities to read and write a state key across many
+instances of a component at once, e.g., for nightly maintenance.
+"""
+
+import contextlib
+import os
+import socket
+from concurrent.futures import ThreadPoolExecutor
+from typing import Any, Callable, Dict, List, Optional, Tuple
+
+import redis
+
+from motion.component import Component
+from motion.dicts import State
+from motion.schema import validate_state
+from motion.utils import LockPolicy, deserializeState, get_redis_params, saveState
+
+# Bounded, so that one stuck instance doesn't stall its chunk forever
+DEFAULT_CHUNK_LOCK_POLICY = LockPolicy.wait(timeout=60)
+# Small, since a write chunk holds the locks of all its instances, blocking
+# their update ops, until the whole chunk is written
+DEFAULT_WRITE_CHUNK_SIZE = 10
+
+
+class MultiInstanceAccessor:
+    def __init__(
+        self,
+        component: Component,
+        instance_ids: List[str] = [],
+        chunk_size: int = 100,
+        num_workers: int = 4,
+        write_chunk_size: int = DEFAULT_WRITE_CHUNK_SIZE,
+        writer_id: str = "",
+    ) -> None:
+        """Creates a MultiInstanceAccessor object, which gets, sets, or
+        deletes a key across many instances of a component. Instances are
+        processed in chunks, with one pipeline per chunk, and up to
+        num_workers chunks are processed in parallel. Writes lock every
+        instance of a chunk until the chunk is written, so up to
+        write_chunk_size * num_workers instance locks are held at once.
+
+        Usage:
+        ```python
+        from motion import MultiInstanceAccessor
+
+        accessor = MultiInstanceAccessor(MyComponent, num_workers=8)
+        accessor.get("threshold") # Returns {"instance_id": value, ...}
+        accessor.set("threshold", 0.8) # Returns the number of instances set
+        accessor.delete("stale_key") # Returns the number of instances
+        # the key was deleted from
+        ```
+
+        Args:
+            component (Component): Component whose instances to access.
+            instance_ids (List[str], optional): Ids of the instances to
+                access. Defaults to empty list, which accesses all instances
+                of the component.
+            chunk_size (int, optional): Number of instances per pipeline
+                when reading. Defaults to 100.
+            num_workers (int, optional): Number of chunks to process in
+                parallel. Defaults to 4.
+            write_chunk_size (int, optional): Number of instances per
+                pipeline when writing, i.e., how many instance locks each
+                worker holds at once. Defaults to 10.
+            writer_id (str, optional): Identifier recorded alongside the
+                writes, retrievable with `last_writer`. Defaults to ""
+                which uses "<hostname>:<pid>".
+
+        Raises:
+            TypeError: if component is not a valid Component
+            ValueError: if chunk_size, write_chunk_size, or num_workers is
+                not positive
+        """
+        if not isinstance(component, Component):
+            raise TypeError("component must be a valid Component")
+
+        if chunk_size <= 0 or write_chunk_size <= 0 or num_workers <= 0:
+            raise ValueError(
+                "chunk_size, write_chunk_size, and num_workers must be positive."
+            )
+
+        self.component = component
+        self.chunk_size = chunk_size
+        self.num_workers = num_workers
+        self.write_chunk_size = write_chunk_size
+        self.writer_id = writer_id or f"{socket.gethostname()}:{os.getpid()}"
+
+        self._key_scheme = component._key_scheme
+        self._env = ":DEV" if os.getenv("MOTION_ENV", "prod") == "dev" else ""
+        self._redis_params = get_redis_params(component._redis_params)
+
+        if instance_ids:
+            instance_names = [f"{component.name}__{iid}" for iid in instance_ids]
+        else:
+            # In dev mode, instances that only have prod state count too
+            redis_con = redis.Redis(**self._redis_params.dict())
+            instance_names = set()
+            for env in {self._env, ""}:
+                version_prefix = f"{self._key_scheme.version}{env}:"
+                instance_names.update(
+                    key.decode("utf-8").replace(version_prefix, "")
+                    for key in redis_con.scan_iter(
+                        f"{version_prefix}{component.name}__*"
+                    )
+                )
+            redis_con.close()
+
+        # Sorted so that each chunk takes its instance locks in a
+        # deterministic order
+        self.instance_names = sorted(instance_names)
+
+    def _run_chunks(self, func: Callable, chunk_size: int) -> List[Any]:
+        chunks = [
+            self.instance_names[i : i + chunk_size]
+            for i in range(0, len(self.instance_names), chunk_size)
+        ]
+
+        def run_chunk(chunk: List[str]) -> Any:
+            redis_con = redis.Redis(**self._redis_params.dict())
+            try:
+                return func(redis_con, chunk)
+            finally:
+                redis_con.close()
+
+        with ThreadPoolExecutor(max_workers=self.num_workers) as executor:
+            return list(executor.map(run_chunk, chunks))
+
+    def _load_chunk(
+        self, redis_con: redis.Redis, chunk: List[str]
+    ) -> List[Tuple[Optional[State], int]]:
+        # Like loadState, dev mode falls back to the prod state
+        envs = [self._env, ""] if self._env else [""]
+        pipeline = redis_con.pipeline()
+        for instance_name in chunk:
+            for env in envs:
+                pipeline.get(f"{self._key_scheme.state}{env}:{instance_name}")
+                pipeline.get(f"{self._key_scheme.version}{env}:{instance_name}")
+        values = pipeline.execute()
+
+        states: List[Tuple[Optional[State], int]] = []
+        num_values = 2 * len(envs)
+        for i, instance_name in enumerate(chunk):
+            instance_values = values[i * num_values : (i + 1) * num_values]
+            found = [
+                (raw_state, raw_version)
+                for raw_state, raw_version in zip(
+                    instance_values[::2], instance_values[1::2]
+                )
+                if raw_state
+            ]
+            if not found:
+                states.append((None, 0))
+                continue
+
+            raw_state, raw_version = found[0]
+            state = deserializeState(
+                raw_state,
+                instance_name,
+                self.component._load_state_func,
+                schema=self.component._schema,
+                on_decode_error=self.component._on_decode_error,
+            )
+            states.append((state, int(raw_version)))
+
+        return states
+
+    def _write(
+        self,
+        key: str,
+        update_func: Callable[[State], bool],
+        lock_policy: Optional[LockPolicy],
+    ) -> int:
+        def write_chunk(redis_con: redis.Redis, chunk: List[str]) -> int:
+            with contextlib.ExitStack() as stack:
+                for instance_name in chunk:
+                    lock_name = f"{self._key_scheme.lock}{self._env}:{instance_name}"
+                    lock = redis_con.lock(lock_name, timeout=120)
+                    (lock_policy or DEFAULT_CHUNK_LOCK_POLICY).acquire(lock)
+                    stack.callback(lock.release)
+
+                version_keys = [
+                    f"{self._key_scheme.version}{self._env}:{instance_name}"
+                    for instance_name in chunk
+                ]
+                while True:
+                    # WATCH the versions so that if a state is saved after
+                    # it is loaded here, nothing in the chunk is written and
+                    # the chunk is loaded again
+                    pipeline = redis_con.pipeline()
+                    pipeline.watch(*version_keys)
+                    pipeline.multi()
+                    num_written = 0
+                    for instance_name, (state, version) in zip(
+                        chunk, self._load_chunk(redis_con, chunk)
+                    ):
+                        # Skip instances that don't exist or aren't changed
+                        if state is None or not update_func(state):
+                            continue
+
+                        deleted = key not in state
+                        new_version = saveState(
+                            state,
+                            version,
+                            redis_con,
+                            instance_name,
+                            self.component._save_state_func,
+                            max_state_bytes=self.component._max_state_bytes,
+                            max_state_keys=self.component._max_state_keys,
+                            schema=self.component._schema,
+                            updated_keys=[] if deleted else [key],
+                            deleted_keys=[key] if deleted else [],
+                            writer_id=self.writer_id,
+                            history_size=self.component._history_size,
+                            pipeline=pipeline,
+                            key_scheme=self._key_scheme,
+                        )
+                        # Saves of states that went stale are dropped
+                        if new_version != -1:
+                            num_written += 1
+                    try:
+                        pipeline.execute()
+                        break
+                    except redis.exceptions.WatchError:
+                        continue
+                    finally:
+                        pipeline.reset()
+
+            return num_written
+
+        return sum(self._run_chunks(write_chunk, self.write_chunk_size))
+
+    def get(self, key: str, default_value: Optional[Any] = None) -> Dict[str, Any]:
+        """Gets the value for the key in each instance's state.
+
+        Args:
+            key (str): Key in the state to get the value for.
+            default_value (Optional[Any], optional): Default value for
+                instances where the key is not found. Defaults to None.
+
+        Returns:
+            Dict[str, Any]: Mapping from instance id to value. Instances
+            that don't exist are left out.
+        """
+
+        def get_chunk(redis_con: redis.Redis, chunk: List[str]) -> Dict[str, Any]:
+            return {
+                instance_name.split("__")[1]: state.get(key, default_value)
+                for instance_name, (state, _) in zip(
+                    chunk, self._load_chunk(redis_con, chunk)
+                )
+                if state is not None
+            }
+
+        values: Dict[str, Any] = {}
+        for chunk_values in self._run_chunks(get_chunk, self.chunk_size):
+            values.update(chunk_values)
+        return values
+
+    def set(
+        self, key: str, value: Any, lock_policy: Optional[LockPolicy] = None
+    ) -> int:
+        """Sets the key to the value in each instance's state, bumping
+        each instance's version. Each chunk holds the locks of its
+        instances while it is written.
+
+        Args:
+            key (str): Key in the state to set.
+            value (Any): Value to set the key to.
+            lock_policy (Optional[LockPolicy], optional): Defaults to waiting
+                up to 60 seconds per lock.
+
+        Raises:
+            SchemaError: If the component has a schema and the key or value
+                does not match it. No instances are updated.
+            ValueError: If an instance's state would exceed the component's
+                limits. Chunks that were already written stay written.
+            redis.exceptions.LockError: If an instance's lock could not be
+                acquired under the lock policy. Its chunk is not written,
+                and chunks that were already written stay written.
+
+        Returns:
+            int: Number of instances set.
+        """
+        if self.component._schema is not None:
+            validate_state(self.component.name, {key: value}, self.component._schema)
+
+        def set_key(state: State) -> bool:
+            state[key] = value
+            return True
+
+        return self._write(key, set_key, lock_policy)
+
+    def delete(self, key: str, lock_policy: Optional[LockPolicy] = None) -> int:
+        """Deletes the key from each instance's state, bumping the version
+        of each instance that had the key.
+
+        Args:
+            key (str): Key in the state to delete.
+            lock_policy (Optional[LockPolicy], optional): Defaults to waiting
+                up to 60 seconds per lock.
+
+        Raises:
+            redis.exceptions.LockError: If an instance's lock could not be
+                acquired under the lock policy. Its chunk is not written,
+                and chunks that were already written stay written.
+
+        Returns:
+            int: Number of instances the key was deleted from.
+        """
+
+        def delete_key(state: State) -> bool:
+            if key not in state:
+                return False
+            del state[key]
+            return True
+
+        return self._write(key, delete_key, lock_policy)
@@ -0,0 +1,76 @@
+"""
+This file contains utilities for declaring and validating the types
+of values stored in a component instance's state.
+"""
+
+from typing import Any, Dict, Tuple, Type, Union
+
+SchemaType = Union[Type, Tuple[Type, ...]]
+
+
+class SchemaError(TypeError):
+    """Raised when a value in a component instance's state does not
+    match the type it is expected to have. The offending key is stored
+    in the `key` attribute."""
+
+    def __init__(self, key: str, message: str) -> None:
+        self.key = key
+        super().__init__(message)
+
+
+def type_name(expected: SchemaType) -> str:
+    if isinstance(expected, tuple):
+        return " or ".join(t.__name__ for t in expected)
+    return expected.__name__
+
+
+def matches_type(value: Any, expected: SchemaType) -> bool:
+    # bool is a subclass of int, but a flag is never a valid int value
+    if isinstance(value, bool):
+        types = expected if isinstance(expected, tuple) else (expected,)
+        return any(t in (bool, object) for t in types)
+
+    return isinstance(value, expected)
+
+
+def check_value(
+    instance_name: str, key: str, value: Any, expected: SchemaType
+) -> None:
+    if not matches_type(value, expected):
+        raise SchemaError(
+            key,
+            f"Key `{key}` in state for instance {instance_name} should be of "
+            + f"type {type_name(expected)}, but got {type(value).__name__}.",
+        )
+
+
+def validate_state(
+    instance_name: str, state: Dict[str, Any], schema: Dict[str, SchemaType]
+) -> None:
+    """Raises a SchemaError if the state has a key that is not declared
+    in the schema or a value of the wrong type."""
+    for key, value in state.items():
+        if key not in schema:
+            raise SchemaError(
+                key,
+                f"Key `{key}` is not declared in the schema for instance "
+                + f"{instance_name}.",
+            )
+        check_value(instance_name, key, value, schema[key])
+
+
+def validate_schema(schema: Dict[str, Any]) -> None:
+    """Raises a TypeError if the schema is not a dict mapping
+    string keys to types (or tuples of types)."""
+    if not isinstance(schema, dict):
+        raise TypeError("schema must be a dict mapping state keys to types.")
+
+    for key, expected in schema.items():
+        if not isinstance(key, str):
+            raise TypeError(f"Schema key {key!r} must be a string.")
+
+        types = expected if isinstance(expected, tuple) else (expected,)
+        if not types or not all(isinstance(t, type) for t in types):
+            raise TypeError(
+                f"Schema entry for key `{key}` must be a type or a tuple of types."
+            )
@@ -1,16 +1,61 @@
 import asyncio
+import contextlib
+import threading
 import time
 import traceback
+import uuid
 from multiprocessing import Process
 from threading import Thread
-from typing import Any, Callable, Dict, List, Optional
+from typing import Any, Callable, Dict, List, Optional, Union
 
 import cloudpickle
 import redis
 import requests
 
 from motion.route import Route
-from motion.utils import FlowOpStatus, loadState, logger, saveState
+from motion.utils import (
+    DEFAULT_KEY_SCHEME,
+    FlowOpStatus,
+    KeyScheme,
+    loadState,
+    logger,
+    saveState,
+)
+
+# An update task's heartbeat expires this many seconds after it dies, after
+# which other update tasks requeue the items it had popped but not finished
+INFLIGHT_HEARTBEAT_TTL = 10
+INFLIGHT_RECOVERY_INTERVAL = 30
+# Seconds an idle update task blocks waiting for a queued item before it
+# checks whether it should stop
+UPDATE_POLL_TIMEOUT = 0.1
+
+
+def requeue_inflight(
+    redis_con: redis.Redis, queue_prefix: str, skip_task_id: Optional[str] = None
+) -> int:
+    """Moves items that dead update tasks popped but didn't finish back to
+    the front of their queues. Items are moved as the pickled bytes they
+    were queued as. Returns the number of items requeued."""
+    inflight_prefix = f"{queue_prefix}/inflight"
+    requeued = 0
+    for key in redis_con.scan_iter(f"{inflight_prefix}/*/*"):
+        if isinstance(key, bytes):
+            key = key.decode("utf-8")
+        task_id, queue_suffix = key[len(inflight_prefix) + 1 :].split("/", 1)
+        if task_id == skip_task_id or redis_con.exists(
+            f"{inflight_prefix}/{task_id}"
+        ):
+            continue
+
+        queue_name = f"{queue_prefix}/{queue_suffix}"
+        while redis_con.lmove(key, queue_name, "RIGHT", "LEFT") is not None:
+            logger.warning(
+                f"Requeued an unfinished update from a dead task on {queue_name}."
+            )
+            requeued += 1
+
+    return requeued
 
 
 class BaseUpdateTask:
@@ -24,9 +69,17 @@ class BaseUpdateTask:
         queue_identifiers: List[str],
         channel_identifiers: Dict[str, str],
         lock_identifier: str,
+        queue_prefix: str,
         redis_params: Dict[str, Any],
         running: Any,
         victoria_metrics_url: Optional[str] = None,
+        max_state_bytes: Optional[int] = None,
+        max_state_keys: Optional[int] = None,
+        schema: Optional[Dict[str, Any]] = None,
+        writer_id: Optional[str] = None,
+        history_size: Optional[int] = None,
+        key_scheme: KeyScheme = DEFAULT_KEY_SCHEME,
+        on_decode_error: Union[str, Callable] = "raise",
     ):
         super().__init__()
         self.task_type = task_type
@@ -39,12 +92,25 @@ class BaseUpdateTask:
 
         self.save_state_func = save_state_func
         self.load_state_func = load_state_func
+        self.max_state_bytes = max_state_bytes
+        self.max_state_keys = max_state_keys
+        self.schema = schema
+        self.writer_id = writer_id
+        self.history_size = history_size
+        self.key_scheme = key_scheme
+        self.on_decode_error = on_decode_error
 
         self.routes = routes
         self.queue_identifiers = queue_identifiers
         self.channel_identifiers = channel_identifiers
         self.lock_identifier = lock_identifier
 
+        # Popped items stay in an in-flight list until they are handled
+        self.task_id = uuid.uuid4().hex
+        self.queue_prefix = queue_prefix
+        self.inflight_prefix = f"{queue_prefix}/inflight"
+        self.heartbeat_key = f"{self.inflight_prefix}/{self.task_id}"
+
         self.running = running
         self.daemon = True
 
@@ -84,18 +150,51 @@ class BaseUpdateTask:
             except requests.RequestException as e:
                 logger.error(f"Failed to send metric to VictoriaMetrics: {e}")
 
+    def _inflightKey(self, queue_name: str) -> str:
+        # e.g., {queue_prefix}/inflight/{task_id}/{route_key}/{udf_name}
+        return (
+            f"{self.inflight_prefix}/{self.task_id}"
+            + queue_name[len(self.queue_prefix) :]
+        )
+
+    def _heartbeat(self, stop_event: threading.Event) -> None:
+        redis_con = redis.Redis(**self.redis_params)
+        try:
+            while not stop_event.wait(INFLIGHT_HEARTBEAT_TTL / 5):
+                try:
+                    redis_con.set(self.heartbeat_key, 1, ex=INFLIGHT_HEARTBEAT_TTL)
+                except redis.exceptions.RedisError:
+                    logger.error("Error refreshing update task heartbeat.")
+        finally:
+            redis_con.close()
+
     def custom_run(self) -> None:
+        stop_heartbeat = threading.Event()
         try:
             redis_con = None
+            last_recovery = 0.0
             while self.running.value:
                 if not redis_con:
                     redis_con = redis.Redis(**self.redis_params)
+                    redis_con.set(self.heartbeat_key, 1, ex=INFLIGHT_HEARTBEAT_TTL)
+                    Thread(
+                        target=self._heartbeat, args=(stop_heartbeat,), daemon=True
+                    ).start()
 
                 item: Dict[str, Any] = {}
                 queue_name = ""
                 try:
-                    # for _ in range(self.batch_size):
-                    full_item = redis_con.blpop(self.queue_identifiers, timeout=0.01)
+                    if time.time() - last_recovery > INFLIGHT_RECOVERY_INTERVAL:
+                        requeue_inflight(
+                            redis_con, self.queue_prefix, skip_task_id=self.task_id
+                        )
+                        last_recovery = time.time()
+
+                    # Blocks on all queues at once; high priority queues are
+                    # listed first, so they are popped first
+                    full_item = redis_con.blpop(
+                        self.queue_identifiers, timeout=UPDATE_POLL_TIMEOUT
+                    )
                     if full_item is None:
                         if not self.running.value:
                             break  # no more items in the list
@@ -103,7 +202,11 @@ class BaseUpdateTask:
                             continue
 
                     queue_name = full_item[0].decode("utf-8")
-                    item = cloudpickle.loads(full_item[1])
+                    raw_item = full_item[1]
+                    # Keep the item in this task's in-flight list until it is
+                    # handled, so it isn't lost if the task dies first
+                    redis_con.rpush(self._inflightKey(queue_name), raw_item)
+                    item = cloudpickle.loads(raw_item)
                     # self.batch.append(item)
                     # if flush_update:
                     #     break
@@ -119,113 +222,131 @@ class BaseUpdateTask:
                 # if not self.batch:
                 #     continue
 
-                exception_str = ""
-                # Check if it was a no op
-                if item["identifier"].startswith("NOOP_"):
-                    redis_con.publish(
-                        self.channel_identifiers[queue_name],
-                        str(
-                            {
-                                "identifier": item["identifier"],
-                                "exception": exception_str,
-                            }
-                        ),
-                    )
-                    continue
-
-                # Check if item.get("expire_at") has passed
-                expire_at = item.get("expire_at")
-                if expire_at is not None:
-                    if expire_at < redis_con.time()[0]:
+                try:
+                    exception_str = ""
+                    # Check if it was a no op
+                    if item["identifier"].startswith("NOOP_"):
                         redis_con.publish(
                             self.channel_identifiers[queue_name],
                             str(
                                 {
                                     "identifier": item["identifier"],
-                                    "exception": "Expired",
+                                    "exception": exception_str,
                                 }
                             ),
                         )
                         continue
 
-                # Run update op
-                try:
-                    start_time = time.time()
-                    with redis_con.lock(self.lock_identifier, timeout=120):
-                        old_state, version = loadState(
-                            redis_con,
-                            self.instance_name,
-                            self.load_state_func,
-                        )
-                        if old_state is None:
-                            # Create new state
-                            # If state does not exist, run setUp
-                            raise ValueError(
-                                f"State for {self.instance_name} not found."
+                    # Check if item.get("expire_at") has passed
+                    expire_at = item.get("expire_at")
+                    if expire_at is not None:
+                        if expire_at < redis_con.time()[0]:
+                            redis_con.publish(
+                                self.channel_identifiers[queue_name],
+                                str(
+                                    {
+                                        "identifier": item["identifier"],
+                                        "exception": "Expired",
+                                    }
+                                ),
                             )
+                            continue
 
-                        state_update = self.routes[queue_name].run(
-                            state=old_state,
-                            props=item["props"],
-                        )
-                        # Await if state_update is a coroutine
-                        if asyncio.iscoroutine(state_update):
-                            state_update = asyncio.run(state_update)
-
-                        if not isinstance(state_update, dict):
-                            logger.error(
-                                "Update methods should return a dict of state updates.",
-                                exc_info=True,
-                            )
-                        else:
-                            old_state.update(state_update)
-                            saveState(
-                                old_state,
-                                version,
+                    # Run update op
+                    try:
+                        start_time = time.time()
+                        with redis_con.lock(self.lock_identifier, timeout=120):
+                            old_state, version = loadState(
                                 redis_con,
                                 self.instance_name,
-                                self.save_state_func,
+                                self.load_state_func,
+                                schema=self.schema,
+                                key_scheme=self.key_scheme,
+                                on_decode_error=self.on_decode_error,
                             )
+                            if old_state is None:
+                                # Create new state
+                                # If state does not exist, run setUp
+                                raise ValueError(
+                                    f"State for {self.instance_name} not found."
+                                )
+
+                            state_update = self.routes[queue_name].run(
+                                state=old_state,
+                                props=item["props"],
+                            )
+                            # Await if state_update is a coroutine
+                            if asyncio.iscoroutine(state_update):
+                                state_update = asyncio.run(state_update)
+
+                            if not isinstance(state_update, dict):
+                                logger.error(
+                                    "Update methods should return a dict of "
+                                    + "state updates.",
+                                    exc_info=True,
+                                )
+                            else:
+                                old_state.update(state_update)
+                                saveState(
+                                    old_state,
+                                    version,
+                                    redis_con,
+                                    self.instance_name,
+                                    self.save_state_func,
+                                    max_state_bytes=self.max_state_bytes,
+                                    max_state_keys=self.max_state_keys,
+                                    schema=self.schema,
+                                    updated_keys=state_update.keys(),
+                                    writer_id=self.writer_id,
+                                    history_size=self.history_size,
+                                    key_scheme=self.key_scheme,
+                                )
+
+                    except Exception:
+                        logger.error(traceback.format_exc())
+                        exception_str = str(traceback.format_exc())
+
+                    duration = time.time() - start_time
 
-                except Exception:
-                    logger.error(traceback.format_exc())
-                    exception_str = str(traceback.format_exc())
-
-                duration = time.time() - start_time
-
-                redis_con.publish(
-                    self.channel_identifiers[queue_name],
-                    str(
-                        {
-                            "identifier": item["identifier"],
-                            "exception": exception_str,
-                        }
-                    ),
-                )
+                    redis_con.publish(
+                        self.channel_identifiers[queue_name],
+                        str(
+                            {
+                                "identifier": item["identifier"],
+                                "exception": exception_str,
+                            }
+                        ),
+                    )
 
-                # Log to VictoriaMetrics
-                if self.victoria_metrics_url:
-                    try:
-                        flow_key = queue_name.split("/")[-2]
-                        udf_name = queue_name.split("/")[-1]
-                        self._logMessage(
-                            flow_key,
-                            "update",
-                            (
-                                FlowOpStatus.SUCCESS
-                                if not exception_str
-                                else FlowOpStatus.FAILURE
-                            ),
-                            duration,
-                            udf_name,
-                        )
-                    except Exception as e:
-                        logger.error(
-                            f"Error logging to VictoriaMetrics: {e}", exc_info=True
-                        )
+                    # Log to VictoriaMetrics
+                    if self.victoria_metrics_url:
+                        try:
+                            flow_key = queue_name.split("/")[-2]
+                            udf_name = queue_name.split("/")[-1]
+                            self._logMessage(
+                                flow_key,
+                                "update",
+                                (
+                                    FlowOpStatus.SUCCESS
+                                    if not exception_str
+                                    else FlowOpStatus.FAILURE
+                                ),
+                                duration,
+                                udf_name,
+                            )
+                        except Exception as e:
+                            logger.error(
+                                f"Error logging to VictoriaMetrics: {e}", exc_info=True
+                            )
+                finally:
+                    redis_con.lrem(self._inflightKey(queue_name), 1, raw_item)
 
         finally:
+            stop_heartbeat.set()
             if redis_con:
+                # Nothing is in flight anymore, so there is nothing to recover
+                with contextlib.suppress(redis.exceptions.RedisError):
+                    redis_con.delete(self.heartbeat_key)
                 redis_con.close()
 
 
@@ -1,18 +1,32 @@
 import hashlib
+import json
 import logging
 import os
 import random
+import re
+import time
 from enum import Enum
 from pathlib import Path
-from typing import Any, Callable, Dict, List, Optional, Tuple
+from typing import (
+    Any,
+    Callable,
+    Dict,
+    Iterable,
+    List,
+    Literal,
+    Optional,
+    Tuple,
+    Union,
+)
 
 import cloudpickle
 import colorlog
 import redis
 import yaml
-from pydantic import BaseModel
+from pydantic import BaseModel, model_validator
 
 from motion.dicts import State
+from motion.schema import validate_state
 
 logger = logging.getLogger(__name__)
 
@@ -59,7 +73,10 @@ class RedisParams(BaseModel, extra="allow"):
         kwargs.setdefault("port", int(os.getenv("MOTION_REDIS_PORT", "6379")))
         kwargs.setdefault("db", int(os.getenv("MOTION_REDIS_DB", "0")))
 
-        if str(os.getenv("MOTION_REDIS_PASSWORD", "None")) != "None":
+        # A password passed in explicitly takes precedence over the env var
+        if kwargs.get("password") is None and (
+            str(os.getenv("MOTION_REDIS_PASSWORD", "None")) != "None"
+        ):
             kwargs["password"] = os.getenv("MOTION_REDIS_PASSWORD")
 
         if str(os.getenv("MOTION_REDIS_SSL", "False")) == "True":
@@ -67,26 +84,280 @@ class RedisParams(BaseModel, extra="allow"):
 
         super().__init__(**kwargs)
 
+    @classmethod
+    def from_redis(cls, redis_con: redis.Redis) -> "RedisParams":
+        """Reads the connection parameters (including SSL, socket, and
+        retry options) off an existing redis.Redis client, so they only
+        need to be defined once.
 
-def get_redis_params() -> RedisParams:
+        Usage:
+        ```python
+        import redis
+        from motion import Component, RedisParams
+
+        redis_con = redis.Redis(host="cache.internal", ssl=True, retry=...)
+        MyComponent = Component(
+            "MyComponent", redis_params=RedisParams.from_redis(redis_con)
+        )
+        ```
+
+        The client's password takes precedence over `MOTION_REDIS_PASSWORD`.
+
+        Args:
+            redis_con (redis.Redis): Client to copy the parameters from.
+
+        Returns:
+            RedisParams: Parameters to connect to the same Redis with.
+        """
+        pool = redis_con.connection_pool
+
+        # Drop unset options, so Motion's defaults (e.g., the socket timeout)
+        # still apply
+        kwargs = {k: v for k, v in pool.connection_kwargs.items() if v is not None}
+
+        # Motion relies on getting bytes back from Redis
+        kwargs.pop("decode_responses", None)
+
+        if issubclass(pool.connection_class, redis.UnixDomainSocketConnection):
+            kwargs["unix_socket_path"] = kwargs.pop("path")
+        if issubclass(pool.connection_class, redis.SSLConnection):
+            kwargs["ssl"] = True
+
+        return cls(**kwargs)
+
+
+def to_redis_params(
+    redis_params: Union["RedisParams", Dict[str, Any], redis.Redis, None]
+) -> Optional[RedisParams]:
+    # Normalizes the connection config a user passes in, if any
+    if redis_params is None or isinstance(redis_params, RedisParams):
+        return redis_params
+    if isinstance(redis_params, redis.Redis):
+        return RedisParams.from_redis(redis_params)
+    if isinstance(redis_params, dict):
+        return RedisParams(**redis_params)
+    raise TypeError(
+        "redis_params must be a RedisParams, a dict of connection kwargs, "
+        + "or a redis.Redis client."
+    )
+
+
+class KeyScheme(BaseModel):
+    """Prefixes of the Redis keys Motion stores data under. For example, the
+    state of an instance is stored at `{state}:{component}__{instance_id}`,
+    or `{state}:DEV:{component}__{instance_id}` in dev mode. Pass a KeyScheme
+    to a Component if the default `MOTION_*` prefixes conflict with other
+    keys in a shared Redis.
+
+    Usage:
+    ```python
+    from motion import Component, KeyScheme
+
+    key_scheme = KeyScheme(state="RECS_STATE", version="RECS_VERSION")
+    MyComponent = Component("MyComponent", key_scheme=key_scheme)
+    ```
+    """
+
+    state: str = "MOTION_STATE"
+    version: str = "MOTION_VERSION"
+    lock: str = "MOTION_LOCK"
+    queue: str = "MOTION_QUEUE"
+    channel: str = "MOTION_CHANNEL"
+    result: str = "MOTION_RESULT"
+    schema_version: str = "MOTION_SCHEMA_VERSION"
+    key_meta: str = "MOTION_KEY_META"
+    access: str = "MOTION_ACCESS"
+    history: str = "MOTION_HISTORY"
+    events: str = "MOTION_EVENTS"
+    components: str = "MOTION_COMPONENTS"
+
+    @model_validator(mode="after")
+    def check_prefixes(self) -> "KeyScheme":
+        prefixes = list(self.dict().values())
+        for prefix in prefixes:
+            if not re.fullmatch(r"[A-Za-z0-9_.-]+", prefix):
+                raise ValueError(
+                    f"Key prefix {prefix!r} may only contain letters, digits, "
+                    + "'_', '.', and '-'."
+                )
+
+        if len(set(prefixes)) != len(prefixes):
+            raise ValueError("Key prefixes in a KeyScheme must be distinct.")
+
+        return self
+
+
+DEFAULT_KEY_SCHEME = KeyScheme()
+
+
+class DuplicateRunError(RuntimeError):
+    """Raised by `run` and its variants when the idempotency key was already
+    used, so the update ops were not queued again. The serve op still ran,
+    and its result is in `serve_result`."""
+
+    def __init__(self, message: str, serve_result: Any = None) -> None:
+        super().__init__(message)
+        self.serve_result = serve_result
+
+
+class LockPolicy(BaseModel):
+    """How to acquire an instance's lock when another process holds it.
+    Create one with `LockPolicy.fail_fast()`, `LockPolicy.bounded(n,
+    backoff)`, or `LockPolicy.wait(timeout)`, and pass it to writes like
+    `write_state`. Connection errors while acquiring the lock, e.g., while
+    Redis restarts, count as failed attempts and are raised as a LockError.
+    To retry failed Redis commands instead, pass a client configured with a
+    `redis.retry.Retry` as the component's `redis_params`.
+
+    Usage:
+    ```python
+    from motion import LockPolicy
+
+    # Raise right away if the lock is held, e.g., in a request handler
+    c_instance.write_state({"value": 1}, lock_policy=LockPolicy.fail_fast())
+
+    # Try 5 times, sleeping 0.1s, 0.2s, 0.4s, ... between attempts
+    c_instance.write_state(
+        {"value": 1}, lock_policy=LockPolicy.bounded(5, backoff=0.1)
+    )
+
+    # Randomize the sleeps so that contending writers spread out, and log
+    # each retry
+    policy = LockPolicy.bounded(
+        5,
+        backoff=0.1,
+        backoff_strategy="decorrelated_jitter",
+        on_retry=lambda attempt, sleep: print(f"Retry {attempt} in {sleep}s"),
+    )
+
+    # Wait up to 30 seconds (or forever, if no timeout is given)
+    c_instance.write_state({"value": 1}, lock_policy=LockPolicy.wait(30))
+    ```
+    """
+
+    mode: Literal["fail_fast", "bounded", "wait"] = "wait"
+    attempts: int = 1
+    backoff: float = 0.1
+    backoff_strategy: Literal[
+        "constant", "exponential", "decorrelated_jitter"
+    ] = "exponential"
+    on_retry: Optional[Callable[[int, float], None]] = None
+    timeout: Optional[float] = None
+
+    @model_validator(mode="after")
+    def check_params(self) -> "LockPolicy":
+        if self.attempts <= 0:
+            raise ValueError("attempts must be positive.")
+        if self.backoff < 0:
+            raise ValueError("backoff must not be negative.")
+        if self.timeout is not None and self.timeout <= 0:
+            raise ValueError("timeout must be positive.")
+        return self
+
+    @classmethod
+    def fail_fast(cls) -> "LockPolicy":
+        return cls(mode="fail_fast")
+
+    @classmethod
+    def bounded(
+        cls,
+        attempts: int,
+        backoff: float = 0.1,
+        backoff_strategy: Literal[
+            "constant", "exponential", "decorrelated_jitter"
+        ] = "exponential",
+        on_retry: Optional[Callable[[int, float], None]] = None,
+    ) -> "LockPolicy":
+        return cls(
+            mode="bounded",
+            attempts=attempts,
+            backoff=backoff,
+            backoff_strategy=backoff_strategy,
+            on_retry=on_retry,
+        )
+
+    @classmethod
+    def wait(cls, timeout: Optional[float] = None) -> "LockPolicy":
+        return cls(mode="wait", timeout=timeout)
+
+    def acquire(self, lock: Any) -> None:
+        attempts = self.attempts if self.mode == "bounded" else 1
+        acquired = False
+        error: Optional[Exception] = None
+        sleep = self.backoff
+        for attempt in range(attempts):
+            try:
+                if self.mode == "wait":
+                    acquired = lock.acquire(
+                        blocking=True, blocking_timeout=self.timeout
+                    )
+                else:
+                    acquired = lock.acquire(blocking=False)
+                error = None
+            except (
+                redis.exceptions.ConnectionError,
+                redis.exceptions.TimeoutError,
+            ) as e:
+                # Counts as a failed attempt. The client reconnects on the
+                # next one, e.g., once a restarted Redis is back up
+                error = e
+
+            if acquired or attempt == attempts - 1:
+                break
+
+            if self.backoff_strategy == "constant":
+                sleep = self.backoff
+            elif self.backoff_strategy == "exponential":
+                sleep = self.backoff * 2**attempt
+            else:
+                sleep = random.uniform(self.backoff, sleep * 3)
+
+            if self.on_retry is not None:
+                self.on_retry(attempt + 1, sleep)
+            time.sleep(sleep)
+
+        if not acquired:
+            raise redis.exceptions.LockError(
+                f"Could not acquire lock {lock.name} with {self!r}"
+                + (f": {error}" if error else ".")
+            ) from error
+
+
+DEFAULT_LOCK_POLICY = LockPolicy()
+
+
+def get_redis_params(
+    redis_params: Union[RedisParams, Dict[str, Any], redis.Redis, None] = None
+) -> RedisParams:
+    # Resolved at connection time, so that the `MOTION_REDIS_*` settings in
+    # .motionrc.yml fill in whatever redis_params leaves out
     import_config()
-    rp = RedisParams()
+    rp = to_redis_params(redis_params) or RedisParams()
     return rp
 
 
-def get_instances(component_name: str) -> List[str]:
+def get_instances(
+    component_name: str,
+    key_scheme: KeyScheme = DEFAULT_KEY_SCHEME,
+    redis_params: Union[RedisParams, Dict[str, Any], redis.Redis, None] = None,
+) -> List[str]:
     """Gets all instances of a component.
 
     Args:
         component_name (str): Name of the component.
+        key_scheme (KeyScheme, optional): Key scheme the component was
+            created with. Defaults to the default KeyScheme.
+        redis_params (Union[RedisParams, Dict[str, Any], redis.Redis, None],
+            optional): Redis the component was created with. Defaults to
+            None, which uses the `MOTION_REDIS_*` environment variables.
 
     Returns:
         List[str]: List of instance ids.
     """
-    rp = get_redis_params()
+    rp = get_redis_params(redis_params)
     redis_con = redis.Redis(**rp.dict())
 
-    instance_ids = redis_con.keys(f"MOTION_VERSION:{component_name}__*")
+    instance_ids = redis_con.keys(f"{key_scheme.version}:{component_name}__*")
     instance_ids = [
         instance_id.decode("utf-8").split("__")[1] for instance_id in instance_ids
     ]
@@ -96,27 +367,36 @@ def get_instances(component_name: str) -> List[str]:
     return instance_ids
 
 
-def clear_dev_instances() -> int:
+def clear_dev_instances(
+    key_scheme: KeyScheme = DEFAULT_KEY_SCHEME,
+    redis_params: Union[RedisParams, Dict[str, Any], redis.Redis, None] = None,
+) -> int:
     """Clears all dev instances."""
-    rp = get_redis_params()
+    rp = get_redis_params(redis_params)
     redis_con = redis.Redis(**rp.dict())
 
     # Scan for all keys with prefix
-    prefix = "MOTION_VERSION:DEV:*"
+    prefix = f"{key_scheme.version}:DEV:*"
     pipeline = redis_con.pipeline()
     num_keys_deleted = 0
     for key in redis_con.scan_iter(prefix):
         pipeline.delete(key)
         num_keys_deleted += 1
 
-    # Delete all states too
-    prefix = "MOTION_STATE:DEV:*"
-    for key in redis_con.scan_iter(prefix):
-        pipeline.delete(key)
-
-    results_to_delete = redis_con.keys("MOTION_RESULT:DEV:*")
-    queues_to_delete = redis_con.keys("MOTION_QUEUE:DEV:*")
-    locks_to_delete = redis_con.keys("MOTION_LOCK:DEV:*")
+    # Delete all states and their metadata too
+    for prefix in [
+        f"{key_scheme.state}:DEV:*",
+        f"{key_scheme.schema_version}:DEV:*",
+        f"{key_scheme.key_meta}:DEV:*",
+        f"{key_scheme.access}:DEV:*",
+        f"{key_scheme.history}:DEV:*",
+    ]:
+        for key in redis_con.scan_iter(prefix):
+            pipeline.delete(key)
+
+    results_to_delete = redis_con.keys(f"{key_scheme.result}:DEV:*")
+    queues_to_delete = redis_con.keys(f"{key_scheme.queue}:DEV:*")
+    locks_to_delete = redis_con.keys(f"{key_scheme.lock}:DEV:*")
     for result in results_to_delete:
         pipeline.delete(result)
     for queue in queues_to_delete:
@@ -132,7 +412,11 @@ def clear_dev_instances() -> int:
     return num_keys_deleted
 
 
-def clear_instance(instance_name: str) -> bool:
+def clear_instance(
+    instance_name: str,
+    key_scheme: KeyScheme = DEFAULT_KEY_SCHEME,
+    redis_params: Union[RedisParams, Dict[str, Any], redis.Redis, None] = None,
+) -> bool:
     """Clears the state and cached results associated with a component instance.
 
     Usage:
@@ -145,6 +429,11 @@ def clear_instance(instance_name: str) -> bool:
     Args:
         instance_name (str): Instance name of the component to clear.
             In the form `componentname__instanceid`.
+        key_scheme (KeyScheme, optional): Key scheme the component was
+            created with. Defaults to the default KeyScheme.
+        redis_params (Union[RedisParams, Dict[str, Any], redis.Redis, None],
+            optional): Redis the component was created with. Defaults to
+            None, which uses the `MOTION_REDIS_*` environment variables.
 
     Raises:
         ValueError:
@@ -157,30 +446,43 @@ def clear_instance(instance_name: str) -> bool:
     if "__" not in instance_name:
         raise ValueError("Instance must be in the form `componentname__instanceid`.")
 
-    rp = get_redis_params()
+    rp = get_redis_params(redis_params)
     redis_con = redis.Redis(
         **rp.dict(),
     )
 
     # Check if the instance exists
-    if not redis_con.exists(f"MOTION_VERSION:{instance_name}") and not redis_con.exists(
-        f"MOTION_VERSION:DEV:{instance_name}"
-    ):
+    if not redis_con.exists(
+        f"{key_scheme.version}:{instance_name}"
+    ) and not redis_con.exists(f"{key_scheme.version}:DEV:{instance_name}"):
         redis_con.close()
         return False
 
     # Delete the instance state, version, and cached results
-    redis_con.delete(f"MOTION_STATE:{instance_name}")
-    redis_con.delete(f"MOTION_STATE:DEV:{instance_name}")
-    redis_con.delete(f"MOTION_VERSION:DEV:{instance_name}")
-    redis_con.delete(f"MOTION_VERSION:{instance_name}")
-    redis_con.delete(f"MOTION_LOCK:{instance_name}")
-    redis_con.delete(f"MOTION_LOCK:DEV:{instance_name}")
+    redis_con.delete(f"{key_scheme.state}:{instance_name}")
+    redis_con.delete(f"{key_scheme.state}:DEV:{instance_name}")
+    redis_con.delete(f"{key_scheme.version}:DEV:{instance_name}")
+    redis_con.delete(f"{key_scheme.version}:{instance_name}")
+    redis_con.delete(f"{key_scheme.lock}:{instance_name}")
+    redis_con.delete(f"{key_scheme.lock}:DEV:{instance_name}")
+    redis_con.delete(f"{key_scheme.schema_version}:{instance_name}")
+    redis_con.delete(f"{key_scheme.schema_version}:DEV:{instance_name}")
+    redis_con.delete(f"{key_scheme.key_meta}:{instance_name}")
+    redis_con.delete(f"{key_scheme.key_meta}:DEV:{instance_name}")
+    redis_con.delete(f"{key_scheme.access}:{instance_name}")
+    redis_con.delete(f"{key_scheme.access}:DEV:{instance_name}")
 
     for env in [":DEV", ""]:
-        results_to_delete = redis_con.keys(f"MOTION_RESULT{env}:{instance_name}/*")
-        queues_to_delete = redis_con.keys(f"MOTION_QUEUE{env}:{instance_name}/*")
-        channels_to_delete = redis_con.keys(f"MOTION_CHANNEL{env}:{instance_name}/*")
+        results_to_delete = redis_con.keys(
+            f"{key_scheme.result}{env}:{instance_name}/*"
+        )
+        queues_to_delete = redis_con.keys(f"{key_scheme.queue}{env}:{instance_name}/*")
+        channels_to_delete = redis_con.keys(
+            f"{key_scheme.channel}{env}:{instance_name}/*"
+        )
+        history_to_delete = redis_con.keys(
+            f"{key_scheme.history}{env}:{instance_name}/*"
+        )
 
         pipeline = redis_con.pipeline()
         for result in results_to_delete:
@@ -189,6 +491,8 @@ def clear_instance(instance_name: str) -> bool:
             pipeline.delete(queue)
         for channel in channels_to_delete:
             pipeline.delete(channel)
+        for history in history_to_delete:
+            pipeline.delete(history)
 
     pipeline.execute()
 
@@ -197,7 +501,11 @@ def clear_instance(instance_name: str) -> bool:
     return True
 
 
-def inspect_state(instance_name: str) -> Optional[State]:
+def inspect_state(
+    instance_name: str,
+    key_scheme: KeyScheme = DEFAULT_KEY_SCHEME,
+    redis_params: Union[RedisParams, Dict[str, Any], redis.Redis, None] = None,
+) -> Optional[State]:
     """
     Returns the state of a component instance.
 
@@ -211,6 +519,11 @@ def inspect_state(instance_name: str) -> Optional[State]:
     Args:
         instance_name (str): Instance name of the component to inspect.
             In the form `componentname__instanceid`.
+        key_scheme (KeyScheme, optional): Key scheme the component was
+            created with. Defaults to the default KeyScheme.
+        redis_params (Union[RedisParams, Dict[str, Any], redis.Redis, None],
+            optional): Redis the component was created with. Defaults to
+            None, which uses the `MOTION_REDIS_*` environment variables.
 
     Raises:
         ValueError:
@@ -223,37 +536,47 @@ def inspect_state(instance_name: str) -> Optional[State]:
     if "__" not in instance_name:
         raise ValueError("Instance must be in the form `componentname__instanceid`.")
 
-    rp = get_redis_params()
+    rp = get_redis_params(redis_params)
     redis_con = redis.Redis(
         **rp.dict(),
     )
 
     # Check if the instance exists
-    if not redis_con.exists(f"MOTION_VERSION:{instance_name}"):
+    if not redis_con.exists(f"{key_scheme.version}:{instance_name}"):
         redis_con.close()
         raise ValueError(f"Instance {instance_name} does not exist.")
 
     # Get the state
-    state, _ = loadState(redis_con, instance_name, None)
+    state, _ = loadState(redis_con, instance_name, None, key_scheme=key_scheme)
 
     redis_con.close()
     return state
 
 
-def get_components() -> List[str]:
+def get_components(
+    key_scheme: KeyScheme = DEFAULT_KEY_SCHEME,
+    redis_params: Union[RedisParams, Dict[str, Any], redis.Redis, None] = None,
+) -> List[str]:
     """Lists all components in the Redis database.
 
+    Args:
+        key_scheme (KeyScheme, optional): Key scheme the components were
+            created with. Defaults to the default KeyScheme.
+        redis_params (Union[RedisParams, Dict[str, Any], redis.Redis, None],
+            optional): Redis the components were created with. Defaults to
+            None, which uses the `MOTION_REDIS_*` environment variables.
+
     Returns:
         List[str]: List of all component names.
     """
 
-    rp = get_redis_params()
+    rp = get_redis_params(redis_params)
     redis_con = redis.Redis(
         **rp.dict(),
     )
 
     component_names = [
-        name.decode("utf-8") for name in redis_con.smembers("MOTION_COMPONENTS")
+        name.decode("utf-8") for name in redis_con.smembers(key_scheme.components)
     ]
 
     redis_con.close()
@@ -261,6 +584,195 @@ def get_components() -> List[str]:
     return component_names
 
 
+def redis_commands(prefix: str) -> List[List[str]]:
+    """Commands that component instances and update tasks send to Redis,
+    with example arguments for keys under the prefix. Keep this in sync
+    when adding Redis calls, since `doctor` checks ACL permissions for
+    exactly these commands."""
+    key = f"{prefix}/key"
+    hash_key = f"{prefix}/hash"
+    queue = f"{prefix}/queue"
+    zset = f"{prefix}/zset"
+    return [
+        # State, versions, and locks
+        ["GET", key],
+        ["SET", key, "motion"],
+        ["SETNX", key, "motion"],
+        ["INCR", key],
+        ["DEL", key],
+        ["EXISTS", key],
+        ["PTTL", key],
+        ["MULTI"],
+        ["EXEC"],
+        ["WATCH", key],
+        ["UNWATCH"],
+        ["EVAL", "return 1", "1", key],
+        ["EVALSHA", "0" * 40, "1", key],
+        ["WAIT", "0", "0"],
+        ["TIME"],
+        ["SCAN", "0", "MATCH", f"{prefix}/*"],
+        ["KEYS", f"{prefix}/*"],
+        ["SADD", f"{prefix}/set", "motion"],
+        ["SMEMBERS", f"{prefix}/set"],
+        # Key metadata and access counts
+        ["HSET", hash_key, "field", "motion"],
+        ["HDEL", hash_key, "field"],
+        ["HGETALL", hash_key],
+        ["HMGET", hash_key, "field"],
+        ["HINCRBY", hash_key, "field", "1"],
+        # Update queues, in-flight lists, and history
+        ["RPUSH", queue, "motion"],
+        ["BLPOP", queue, "0"],
+        ["LMOVE", queue, f"{prefix}/inflight", "RIGHT", "LEFT"],
+        ["LREM", queue, "1", "motion"],
+        ["LRANGE", queue, "0", "-1"],
+        ["LLEN", queue],
+        ["LTRIM", queue, "0", "-1"],
+        # Scheduled writes
+        ["ZADD", zset, "0", "motion"],
+        ["ZRANGEBYSCORE", zset, "-inf", "+inf"],
+        ["ZREM", zset, "motion"],
+        # State change events
+        ["PUBLISH", f"{prefix}/channel", "motion"],
+        ["SUBSCRIBE", f"{prefix}/channel"],
+    ]
+
+
+def doctor(
+    redis_params: Union[RedisParams, Dict[str, Any], redis.Redis, None] = None,
+    key_scheme: KeyScheme = DEFAULT_KEY_SCHEME,
+) -> List[Dict[str, Any]]:
+    """Checks that Motion can use the configured Redis: that it can
+    connect and authenticate, how long a round trip takes, that the ACL
+    user may run the commands Motion uses, that it can read and write
+    keys, publish events, and take locks, and how keyspace notifications
+    are configured. Use this to debug host, port, password, or SSL
+    misconfigurations.
+
+    Usage:
+    ```python
+    from motion.utils import doctor
+
+    for check in doctor():
+        print(check["check"], check["ok"], check["detail"])
+    ```
+
+    Or, from the command line: `motion doctor`.
+
+    Args:
+        redis_params (Union[RedisParams, Dict[str, Any], redis.Redis, None],
+            optional): Redis to check. Defaults to None, which uses the
+            `MOTION_REDIS_*` environment variables.
+        key_scheme (KeyScheme, optional): Key scheme whose lock prefix the
+            probe keys are written under. Defaults to the default KeyScheme.
+
+    Returns:
+        List[Dict[str, Any]]: One entry per check, with the `check` name,
+        whether it is `ok`, and a `detail` message. `ok` is None if Redis
+        doesn't allow the commands a check needs, as is common on managed
+        Redis services. Checks after a failed connection are skipped.
+    """
+    rp = get_redis_params(redis_params)
+    target = f"{rp.host}:{rp.port}/{rp.db}" + (" (ssl)" if rp.ssl else "")
+    report: List[Dict[str, Any]] = []
+
+    def check(name: str, func: Callable[[], str]) -> bool:
+        try:
+            report.append({"check": name, "ok": True, "detail": func()})
+            return True
+        except redis.exceptions.RedisError as e:
+            report.append(
+                {"check": name, "ok": False, "detail": f"{type(e).__name__}: {e}"}
+            )
+            return False
+
+    def check_if_allowed(name: str, func: Callable[[], Tuple[bool, str]]) -> None:
+        ok: Optional[bool]
+        try:
+            ok, detail = func()
+        except redis.exceptions.ResponseError as e:
+            ok, detail = None, f"Could not check: {type(e).__name__}: {e}"
+        except redis.exceptions.RedisError as e:
+            ok, detail = False, f"{type(e).__name__}: {e}"
+        report.append({"check": name, "ok": ok, "detail": detail})
+
+    redis_con = redis.Redis(**rp.dict())
+    try:
+
+        def ping() -> str:
+            redis_con.ping()
+            return f"Connected to {target}."
+
+        if not check("connection", ping):
+            return report
+
+        def round_trip() -> str:
+            durations = []
+            for _ in range(5):
+                start = time.time()
+                redis_con.ping()
+                durations.append(time.time() - start)
+            return f"Median round trip is {sorted(durations)[2] * 1000:.2f}ms."
+
+        check("latency", round_trip)
+
+        probe = f"{key_scheme.lock}:doctor:{random_passphrase()}"
+
+        def permissions() -> Tuple[bool, str]:
+            # ACL DRYRUN needs Redis 7, and may itself be disallowed
+            user = redis_con.acl_whoami()
+            if isinstance(user, bytes):
+                user = user.decode("utf-8")
+            denied = [
+                command[0]
+                for command in redis_commands(probe)
+                if redis_con.execute_command("ACL", "DRYRUN", user, *command)
+                not in ["OK", b"OK"]
+            ]
+            if denied:
+                return False, f"User {user} may not run {', '.join(denied)}."
+            return True, f"User {user} may run the commands Motion uses."
+
+        def keyspace_notifications() -> Tuple[bool, str]:
+            # Informational: Motion publishes its own state change events
+            value = redis_con.config_get("notify-keyspace-events").get(
+                "notify-keyspace-events", ""
+            )
+            if isinstance(value, bytes):
+                value = value.decode("utf-8")
+            if not value:
+                return True, "Keyspace notifications are off."
+            return True, f"Keyspace notifications are set to {value!r}."
+
+        def read_write() -> str:
+            redis_con.set(f"{probe}/key", b"motion", ex=60)
+            if redis_con.get(f"{probe}/key") != b"motion":
+                raise redis.exceptions.ResponseError("Read back a different value.")
+            redis_con.delete(f"{probe}/key")
+            return "Can read and write keys."
+
+        def publish() -> str:
+            redis_con.publish(f"{probe}/channel", "motion")
+            return "Can publish state change events."
+
+        def lock() -> str:
+            lock = redis_con.lock(f"{probe}/lock", timeout=5)
+            if not lock.acquire(blocking=False):
+                raise redis.exceptions.LockError("Probe lock is already held.")
+            lock.release()
+            return "Can acquire and release instance locks."
+
+        check_if_allowed("permissions", permissions)
+        check("read_write", read_write)
+        check("publish", publish)
+        check("lock", lock)
+        check_if_allowed("keyspace_notifications", keyspace_notifications)
+    finally:
+        redis_con.close()
+
+    return report
+
+
 def validate_args(parameters: Any, op: str) -> bool:
     if "state" not in parameters.keys():
         return False
@@ -298,44 +810,122 @@ def configureLogging(level: str) -> None:
     logger.setLevel(level)
 
 
-def loadState(
+def loadRawState(
     redis_con: redis.Redis,
     instance_name: str,
-    load_state_func: Optional[Callable],
-) -> Tuple[Optional[State], int]:
-    # Get state from redis
-    state = State(instance_name.split("__")[0], instance_name.split("__")[1], {})
-
+    key_scheme: KeyScheme = DEFAULT_KEY_SCHEME,
+) -> Tuple[Optional[bytes], int]:
     # If dev mode, load with diff prefix
     loaded_state = None
     version = None
     if os.getenv("MOTION_ENV", "prod") == "dev":
-        loaded_state = redis_con.get(f"MOTION_STATE:DEV:{instance_name}")
+        loaded_state = redis_con.get(f"{key_scheme.state}:DEV:{instance_name}")
         if loaded_state:
-            v_identifier = f"MOTION_VERSION:DEV:{instance_name}"
+            v_identifier = f"{key_scheme.version}:DEV:{instance_name}"
             version = int(redis_con.get(v_identifier))  # type: ignore
         else:
-            loaded_state = redis_con.get(f"MOTION_STATE:{instance_name}")
+            loaded_state = redis_con.get(f"{key_scheme.state}:{instance_name}")
 
     else:
-        loaded_state = redis_con.get(f"MOTION_STATE:{instance_name}")
+        loaded_state = redis_con.get(f"{key_scheme.state}:{instance_name}")
 
     if not loaded_state:
-        # This is an error
-        logger.warning(f"Could not find state for {instance_name}. Creating new state.")
         return None, 0
 
     if not version:
-        version = int(redis_con.get(f"MOTION_VERSION:{instance_name}"))  # type: ignore
+        v_identifier = f"{key_scheme.version}:{instance_name}"
+        version = int(redis_con.get(v_identifier))  # type: ignore
+
+    return loaded_state, version
 
-    # Unpickle state
-    loaded_state = cloudpickle.loads(loaded_state)
+
+def deserializeState(
+    raw_state: bytes,
+    instance_name: str,
+    load_state_func: Optional[Callable],
+    schema: Optional[Dict[str, Any]] = None,
+    on_decode_error: Union[str, Callable] = "raise",
+) -> State:
+    state = State(
+        instance_name.split("__")[0],
+        instance_name.split("__")[1],
+        {},
+        schema=schema,
+    )
+
+    try:
+        loaded_state = cloudpickle.loads(raw_state)
+    except Exception as e:
+        if on_decode_error == "raise":
+            raise
+
+        # Recover instead of blocking all access to the instance
+        logger.error(f"Could not decode state for {instance_name}: {e}")
+        if on_decode_error != "skip":
+            recovered = on_decode_error(  # type: ignore
+                instance_name.split("__")[1], raw_state, e
+            )
+            if recovered is not None and not isinstance(recovered, dict):
+                raise TypeError(
+                    "on_decode_error must return a dict or None, not "
+                    + f"{type(recovered).__name__}."
+                )
+            if recovered is not None:
+                state.update(recovered)
+                return state
+
+        # Saving the partial state would overwrite the stored bytes, so it
+        # can't be written until the stored state is repaired
+        state._read_only = True
+        return state
 
     if load_state_func is not None:
         state.update(load_state_func(loaded_state))
     else:
         state.update(loaded_state)
 
+    return state
+
+
+def loadState(
+    redis_con: redis.Redis,
+    instance_name: str,
+    load_state_func: Optional[Callable],
+    schema: Optional[Dict[str, Any]] = None,
+    key_scheme: KeyScheme = DEFAULT_KEY_SCHEME,
+    op_info: Optional[Dict[str, Any]] = None,
+    on_decode_error: Union[str, Callable] = "raise",
+) -> Tuple[Optional[State], int]:
+    # If op_info is passed in, it is filled with the serializer used and
+    # the size and time it took to deserialize the state
+    # Get state from redis
+    loaded_state, version = loadRawState(redis_con, instance_name, key_scheme)
+    if not loaded_state:
+        # This is an error
+        logger.warning(f"Could not find state for {instance_name}. Creating new state.")
+        return None, 0
+
+    # Unpickle state
+    start = time.time()
+    state = deserializeState(
+        loaded_state,
+        instance_name,
+        load_state_func,
+        schema=schema,
+        on_decode_error=on_decode_error,
+    )
+
+    if op_info is not None:
+        op_info.update(
+            {
+                "op": "load",
+                "serializer": "cloudpickle",
+                "bytes": len(loaded_state),
+                "duration": time.time() - start,
+                "keys": list(state.keys()),
+            }
+        )
+
     return state, version
 
 
@@ -345,35 +935,249 @@ def saveState(
     redis_con: redis.Redis,
     instance_name: str,
     save_state_func: Optional[Callable],
+    max_state_bytes: Optional[int] = None,
+    max_state_keys: Optional[int] = None,
+    schema: Optional[Dict[str, Any]] = None,
+    updated_keys: Optional[Iterable[str]] = None,
+    deleted_keys: Optional[Iterable[str]] = None,
+    writer_id: Optional[str] = None,
+    history_size: Optional[int] = None,
+    pipeline: Optional[redis.client.Pipeline] = None,
+    key_scheme: KeyScheme = DEFAULT_KEY_SCHEME,
+    op_info: Optional[Dict[str, Any]] = None,
+    serialized_state: Optional[bytes] = None,
 ) -> int:
-    # If the version in redis is greater than this version, drop the save
-    redis_v = None
-    if os.getenv("MOTION_ENV", "prod") == "dev":
-        redis_con.get(f"MOTION_VERSION:DEV:{instance_name}")
+    # If a pipeline is passed in, the writes are queued on it and the caller
+    # is responsible for executing it
+    # If op_info is passed in, it is filled with the serializer used and
+    # the size and time it took to serialize the state
+    # If serialized_state is passed in, it is stored as is instead of
+    # serializing state_to_save, which must be what it deserializes to
+    if getattr(state_to_save, "_read_only", False):
+        raise ValueError(
+            f"State for {instance_name} could not be decoded, so it is "
+            + "read-only. Repair it with set_raw_state before writing."
+        )
 
-    if not redis_v:
-        redis_v = redis_con.get(f"MOTION_VERSION:{instance_name}")
+    # Enforce the schema and keyspace quota before anything is sent to redis
+    if schema is not None:
+        validate_state(instance_name, state_to_save, schema)
 
-    if redis_v and int(redis_v) > version:
-        # This means that another process has already saved the state
-        # Return a sentinel value that indicates that the state was not saved
-        return -1
+    if max_state_keys is not None and len(state_to_save) > max_state_keys:
+        raise ValueError(
+            f"State for {instance_name} has {len(state_to_save)} keys, which "
+            + f"exceeds max_state_keys={max_state_keys}. State was not saved."
+        )
 
-    # Save state to redis
-    if save_state_func is not None:
-        state_to_save = save_state_func(state_to_save)
+    env = ":DEV" if os.getenv("MOTION_ENV", "prod") == "dev" else ""
+    version_key = f"{key_scheme.version}{env}:{instance_name}"
+    meta_key = f"{key_scheme.key_meta}{env}:{instance_name}"
+    history_prefix = f"{key_scheme.history}{env}:{instance_name}"
+    events_channel = f"{key_scheme.events}{env}:{instance_name}"
+
+    # WATCH the version key so that if another writer saves between the
+    # version check and EXEC, the transaction is aborted and the save is
+    # dropped. Callers that pass in a pipeline must WATCH the version key
+    # themselves and call multi() before calling this.
+    execute_pipeline = pipeline is None
+    if pipeline is None:
+        pipeline = redis_con.pipeline()
+        pipeline.watch(version_key)
+
+    try:
+        # If the version in redis is greater than this version, drop the save
+        stored_v = redis_con.get(version_key)
+        redis_v = stored_v
+        if not redis_v and env:
+            redis_v = redis_con.get(f"{key_scheme.version}:{instance_name}")
+
+        if redis_v and int(redis_v) > version:
+            # This means that another process has already saved the state
+            # Return a sentinel value that indicates that the state was not
+            # saved
+            return -1
+
+        # The version the INCR below will return. SETNX covers the first
+        # save, and a dev save on top of prod state. The key is watched, so
+        # nothing else can change it before EXEC.
+        new_version = (int(stored_v) if stored_v else version) + 1
+
+        updated_fields = [
+            key for key in updated_keys or [] if isinstance(key, str)
+        ]
+        deleted_fields = [
+            key for key in deleted_keys or [] if isinstance(key, str)
+        ]
+
+        # Serialize history entries before save_state_func transforms the state
+        history_entries = []
+        if history_size:
+            history_entries = [
+                (
+                    key,
+                    cloudpickle.dumps(
+                        {
+                            "version": new_version,
+                            "value": dict.__getitem__(state_to_save, key),
+                        }
+                    ),
+                )
+                for key in updated_fields
+            ] + [
+                (
+                    key,
+                    cloudpickle.dumps({"version": new_version, "deleted": True}),
+                )
+                for key in deleted_fields
+            ]
+
+        # Save state to redis
+        start = time.time()
+        if serialized_state is not None:
+            state_pickled = serialized_state
+        else:
+            if save_state_func is not None:
+                state_to_save = save_state_func(state_to_save)
+            state_pickled = cloudpickle.dumps(state_to_save)
+
+        if op_info is not None:
+            op_info.update(
+                {
+                    "op": "save",
+                    "serializer": (
+                        "raw" if serialized_state is not None else "cloudpickle"
+                    ),
+                    "bytes": len(state_pickled),
+                    "duration": time.time() - start,
+                    "keys": updated_fields + deleted_fields,
+                }
+            )
+
+        if (
+            max_state_bytes is not None
+            and len(state_pickled) > max_state_bytes
+        ):
+            raise ValueError(
+                f"State for {instance_name} is {len(state_pickled)} bytes when "
+                + "serialized, which exceeds "
+                + f"max_state_bytes={max_state_bytes}. State was not saved."
+            )
+
+        if execute_pipeline:
+            pipeline.multi()
+        pipeline.set(f"{key_scheme.state}{env}:{instance_name}", state_pickled)
+
+        # Increment the stored version rather than setting it, so a writer
+        # whose lock expired can't move it backwards
+        pipeline.setnx(version_key, version)
+        version_index = len(pipeline)
+        pipeline.incr(version_key)
+
+        # Record when and by whom each written key was last modified, in the
+        # same transaction
+        key_meta = json.dumps(
+            {
+                "modified_at": time.time(),
+                "version": new_version,
+                "writer": writer_id,
+            }
+        )
+        if updated_fields:
+            pipeline.hset(
+                meta_key, mapping={key: key_meta for key in updated_fields}
+            )
+        if deleted_fields:
+            pipeline.hdel(meta_key, *deleted_fields)
+
+        # Keep the last history_size values of each written key
+        for key, entry in history_entries:
+            pipeline.rpush(f"{history_prefix}/{key}", entry)
+            pipeline.ltrim(
+                f"{history_prefix}/{key}", -history_size, -1  # type: ignore
+            )
+
+        # Let subscribers know which keys changed
+        if updated_fields or deleted_fields:
+            pipeline.publish(
+                events_channel,
+                json.dumps(
+                    {
+                        "updated_keys": updated_fields,
+                        "deleted_keys": deleted_fields,
+                        "version": new_version,
+                        "writer": writer_id,
+                    }
+                ),
+            )
+
+        if execute_pipeline:
+            try:
+                return int(pipeline.execute()[version_index])
+            except redis.exceptions.WatchError:
+                # Another writer saved first
+                return -1
+
+        return new_version
+    finally:
+        if execute_pipeline:
+            pipeline.reset()
+
+
+def loadKeyMeta(
+    redis_con: redis.Redis,
+    instance_name: str,
+    keys: Optional[List[str]] = None,
+    key_scheme: KeyScheme = DEFAULT_KEY_SCHEME,
+) -> Dict[str, Dict[str, Any]]:
+    # If dev mode, load with diff prefix
+    meta_key = f"{key_scheme.key_meta}:{instance_name}"
+    if os.getenv("MOTION_ENV", "prod") == "dev" and redis_con.exists(
+        f"{key_scheme.state}:DEV:{instance_name}"
+    ):
+        meta_key = f"{key_scheme.key_meta}:DEV:{instance_name}"
 
-    state_pickled = cloudpickle.dumps(state_to_save)
+    if keys is None:
+        raw_meta = {
+            field.decode("utf-8"): value
+            for field, value in redis_con.hgetall(meta_key).items()
+        }
+    else:
+        raw_meta = dict(zip(keys, redis_con.hmget(meta_key, keys))) if keys else {}
 
-    if os.getenv("MOTION_ENV", "prod") == "dev":
-        redis_con.set(f"MOTION_STATE:DEV:{instance_name}", state_pickled)
-        redis_con.set(f"MOTION_VERSION:DEV:{instance_name}", version + 1)
+    return {key: json.loads(value) for key, value in raw_meta.items() if value}
 
-    else:
-        redis_con.set(f"MOTION_STATE:{instance_name}", state_pickled)
-        redis_con.set(f"MOTION_VERSION:{instance_name}", version + 1)
 
-    return version + 1
+def _historyPrefix(
+    redis_con: redis.Redis, instance_name: str, key_scheme: KeyScheme
+) -> str:
+    # If dev mode, load with diff prefix
+    if os.getenv("MOTION_ENV", "prod") == "dev" and redis_con.exists(
+        f"{key_scheme.state}:DEV:{instance_name}"
+    ):
+        return f"{key_scheme.history}:DEV:{instance_name}"
+    return f"{key_scheme.history}:{instance_name}"
+
+
+def loadKeyHistory(
+    redis_con: redis.Redis,
+    instance_name: str,
+    key: str,
+    key_scheme: KeyScheme = DEFAULT_KEY_SCHEME,
+) -> List[Dict[str, Any]]:
+    history_key = f"{_historyPrefix(redis_con, instance_name, key_scheme)}/{key}"
+    return [cloudpickle.loads(entry) for entry in redis_con.lrange(history_key, 0, -1)]
+
+
+def loadHistoryKeys(
+    redis_con: redis.Redis,
+    instance_name: str,
+    key_scheme: KeyScheme = DEFAULT_KEY_SCHEME,
+) -> List[str]:
+    history_prefix = _historyPrefix(redis_con, instance_name, key_scheme)
+    return [
+        history_key.decode("utf-8")[len(history_prefix) + 1 :]
+        for history_key in redis_con.scan_iter(f"{history_prefix}/*")
+    ]
 
 
 class UpdateEvent:
@@ -0,0 +1,59 @@
+from motion import Component, SchemaError
+
+import pytest
+
+C = Component("BroadcastComponent", schema={"value": int, "threshold": float})
+
+
+@C.init_state
+def setUp():
+    return {"value": 0}
+
+
+@C.serve("get_threshold")
+def get_threshold(state, props):
+    return state.get("threshold")
+
+
+def test_broadcast_set():
+    instances = [C(f"instance_{i}") for i in range(5)]
+    versions = [instance.get_version() for instance in instances]
+
+    assert C.broadcast_set("threshold", 0.8, chunk_size=2) == 5
+
+    for instance, version in zip(instances, versions):
+        assert instance.read_state("threshold") == 0.8
+        assert instance.read_state("value") == 0
+        assert instance.get_version() == version + 1
+        assert instance.run("get_threshold", ignore_cache=True) == 0.8
+
+
+def test_broadcast_set_checks_schema():
+    instance = C("instance_schema")
+    with pytest.raises(SchemaError):
+        C.broadcast_set("threshold", "high")
+
+    with pytest.raises(SchemaError, match="not declared"):
+        C.broadcast_set("undeclared", 1)
+
+    assert instance.read_state("undeclared") is None
+
+
+def test_get_many_instances():
+    instances = [C(f"many_{i}") for i in range(5)]
+    instances[0].write_state({"threshold": 0.5})
+
+    values = C.get_many_instances(
+        "threshold", [f"many_{i}" for i in range(5)] + ["missing"], chunk_size=2
+    )
+    assert values == {
+        "many_0": 0.5,
+        "many_1": None,
+        "many_2": None,
+        "many_3": None,
+        "many_4": None,
+    }
+
+    values = C.get_many_instances("value", default_value=-1)
+    assert values["many_3"] == 0
+    assert len(values) >= len(instances)
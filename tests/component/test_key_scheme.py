@@ -0,0 +1,71 @@
+from motion import Component, KeyScheme, clear_instance, get_instances
+from motion.utils import get_redis_params
+
+import pytest
+import redis
+
+key_scheme = KeyScheme(
+    state="RECS_STATE",
+    version="RECS_VERSION",
+    lock="RECS_LOCK",
+    queue="RECS_QUEUE",
+    channel="RECS_CHANNEL",
+    result="RECS_RESULT",
+)
+
+C = Component("SchemedComponent", key_scheme=key_scheme)
+
+
+@C.init_state
+def setUp():
+    return {"value": 0}
+
+
+@C.serve("add")
+def serve_add(state, props):
+    return state["value"] + props["value"]
+
+
+@C.update("add")
+def update_add(state, props):
+    return {"value": state["value"] + props["value"]}
+
+
+def test_key_scheme():
+    c_instance = C("custom")
+    assert c_instance.run("add", props={"value": 1}, flush_update=True) == 1
+    assert c_instance.read_state("value") == 1
+
+    rp = get_redis_params()
+    r = redis.Redis(
+        host=rp.host,
+        port=rp.port,
+        password=rp.password,
+        db=rp.db,
+    )
+    assert r.exists("RECS_STATE:SchemedComponent__custom")
+    assert r.exists("RECS_VERSION:SchemedComponent__custom")
+    assert list(r.scan_iter("RECS_RESULT:SchemedComponent__custom/*"))
+    for prefix in ["MOTION_STATE", "MOTION_VERSION", "MOTION_RESULT"]:
+        assert not list(r.scan_iter(f"{prefix}:SchemedComponent__*"))
+
+    assert get_instances("SchemedComponent", key_scheme) == ["custom"]
+    assert get_instances("SchemedComponent") == []
+    assert C.peek("custom", "value") == 1
+
+    assert clear_instance("SchemedComponent__custom", key_scheme)
+    assert not r.exists("RECS_STATE:SchemedComponent__custom")
+
+
+def test_invalid_key_scheme():
+    with pytest.raises(ValueError):
+        KeyScheme(state="BAD:PREFIX")
+
+    with pytest.raises(ValueError):
+        KeyScheme(state="")
+
+    with pytest.raises(ValueError):
+        KeyScheme(state="SAME", version="SAME")
+
+    with pytest.raises(TypeError):
+        Component("BadScheme", key_scheme={"state": "RECS_STATE"})
@@ -0,0 +1,51 @@
+from motion import Component
+
+import cloudpickle
+import pytest
+import redis
+
+Config = Component("GlobalConfig")
+
+
+@Config.init_state
+def setUp():
+    return {"threshold": 0.7}
+
+
+Classifier = Component("Classifier")
+
+
+@Classifier.serve("classify")
+def classify(state, props):
+    return props["score"] > Config.peek("global", "threshold", 0.5)
+
+
+def test_peek():
+    with Config("global") as config:
+        classifier = Classifier()
+        assert classifier.run("classify", props={"score": 0.6}) is False
+
+        config.write_state({"threshold": 0.2})
+        assert Config.peek("global", "threshold") == 0.2
+        assert Config.peek("global", "missing", "default") == "default"
+        assert classifier.run("classify", props={"score": 0.3}) is True
+
+
+def test_peek_missing_instance():
+    with pytest.raises(ValueError):
+        Config.peek("does_not_exist", "threshold")
+
+
+def test_peek_reuses_client(monkeypatch):
+    with Config("global"):
+        threshold = Config.peek("global", "threshold")
+
+        clients = []
+        monkeypatch.setattr(redis, "Redis", lambda **kwargs: clients.append(kwargs))
+        for _ in range(3):
+            assert Config.peek("global", "threshold") == threshold
+        assert clients == []
+
+        # Pickling drops the clients, which are created again when needed
+        unpickled = cloudpickle.loads(cloudpickle.dumps(Config))
+        assert unpickled._peek_clients == {}
@@ -0,0 +1,50 @@
+from motion import Component, RedisParams
+
+import pytest
+import redis
+
+
+def test_redis_params_from_client():
+    redis_con = redis.Redis(
+        host="localhost", port=6379, db=0, socket_timeout=5, decode_responses=True
+    )
+    rp = RedisParams.from_redis(redis_con)
+    assert rp.host == "localhost"
+    assert rp.port == 6379
+    assert rp.dict()["socket_timeout"] == 5
+    assert "decode_responses" not in rp.dict()
+
+    ssl_con = redis.Redis(host="localhost", ssl=True)
+    assert RedisParams.from_redis(ssl_con).ssl
+
+
+def test_redis_params_from_client_precedence(monkeypatch):
+    monkeypatch.setenv("MOTION_REDIS_PASSWORD", "env_password")
+    redis_con = redis.Redis(
+        host="localhost", password="client_password", socket_timeout=None
+    )
+    rp = RedisParams.from_redis(redis_con)
+    assert rp.password == "client_password"
+
+    # Unset client options don't block Motion's defaults
+    assert "socket_timeout" not in rp.dict()
+    assert RedisParams.from_redis(redis.Redis()).password == "env_password"
+
+
+def test_component_redis_params():
+    redis_con = redis.Redis(host="localhost", port=6379, db=0)
+
+    for redis_params in [redis_con, {"host": "localhost"}, RedisParams()]:
+        C = Component("RedisParamsComponent", redis_params=redis_params)
+
+        @C.init_state
+        def setUp():
+            return {"value": 0}
+
+        c_instance = C()
+        c_instance.write_state({"value": 1})
+        assert C.peek(c_instance.instance_id, "value") == 1
+        assert c_instance._executor._redis_params.host == "localhost"
+
+    with pytest.raises(TypeError):
+        Component("BadRedisParams", redis_params="localhost:6379")
@@ -0,0 +1,77 @@
+from motion import Component
+
+import pytest
+import threading
+import time
+
+C = Component("GetAndUpdateComponent")
+
+
+@C.init_state
+def setUp():
+    return {"count": 0}
+
+
+def test_get_and_update():
+    c_instance = C()
+    assert c_instance.get_and_update("count", lambda c: c + 1) == 1
+    assert c_instance.get_and_update("missing", lambda v: v + ["a"], []) == ["a"]
+    assert c_instance.read_state("count") == 1
+
+
+def test_get_and_update_concurrent():
+    c_instance = C()
+    handles = [C(c_instance.instance_id) for _ in range(4)]
+
+    def increment(handle):
+        for _ in range(10):
+            handle.get_and_update("count", lambda c: c + 1)
+
+    threads = [threading.Thread(target=increment, args=(h,)) for h in handles]
+    for thread in threads:
+        thread.start()
+    for thread in threads:
+        thread.join()
+
+    assert c_instance.read_state("count") == 40
+
+
+def test_get_and_update_timeout():
+    c_instance = C()
+    version = c_instance.get_version()
+
+    def slow(c):
+        time.sleep(1)
+        return c + 1
+
+    with pytest.raises(TimeoutError):
+        c_instance.get_and_update("count", slow, timeout=0.1)
+
+    with pytest.raises(ZeroDivisionError):
+        c_instance.get_and_update("count", lambda c: 1 / c)
+
+    assert c_instance.read_state("count") == 0
+    assert c_instance.get_version() == version
+
+    # The lock was released
+    assert c_instance.get_and_update("count", lambda c: c + 1) == 1
+
+
+def test_get_and_update_mutates_after_timeout():
+    c_instance = C()
+    c_instance.write_state({"seen": ["a"]})
+    done = threading.Event()
+
+    def slow_append(seen):
+        time.sleep(0.3)
+        seen.append("b")
+        done.set()
+        return seen
+
+    with pytest.raises(TimeoutError):
+        c_instance.get_and_update("seen", slow_append, timeout=0.1)
+
+    # The late in-place change doesn't leak into the cached or stored state
+    assert done.wait(2)
+    assert c_instance.read_state("seen") == ["a"]
+    assert C.peek(c_instance.instance_id, "seen") == ["a"]
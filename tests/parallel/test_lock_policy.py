@@ -0,0 +1,106 @@
+from motion import Component, LockPolicy
+
+import pytest
+import redis
+import time
+
+C = Component("LockPolicyComponent")
+
+
+@C.init_state
+def setUp():
+    return {"value": 0}
+
+
+def hold_lock(c_instance):
+    redis_con = c_instance._executor._redis_con
+    lock_name = c_instance._executor._Executor__lock_prefix
+    lock = redis_con.lock(lock_name, timeout=120)
+    lock.acquire()
+    return lock
+
+
+def test_lock_policies():
+    c_instance = C()
+    lock = hold_lock(c_instance)
+    version = c_instance.get_version()
+
+    with pytest.raises(redis.exceptions.LockError):
+        c_instance.write_state({"value": 1}, lock_policy=LockPolicy.fail_fast())
+
+    start = time.time()
+    with pytest.raises(redis.exceptions.LockError):
+        c_instance.write_state(
+            {"value": 1}, lock_policy=LockPolicy.bounded(3, backoff=0.05)
+        )
+    # Sleeps 0.05s and 0.1s between the three attempts
+    assert time.time() - start >= 0.15
+
+    with pytest.raises(redis.exceptions.LockError):
+        c_instance.pop_state("value", lock_policy=LockPolicy.wait(0.1))
+
+    assert c_instance.read_state("value") == 0
+    assert c_instance.get_version() == version
+
+    lock.release()
+    c_instance.write_state({"value": 1}, lock_policy=LockPolicy.fail_fast())
+    assert c_instance.read_state("value") == 1
+
+
+def test_invalid_lock_policy():
+    with pytest.raises(ValueError):
+        LockPolicy.bounded(0)
+
+    with pytest.raises(ValueError):
+        LockPolicy.wait(-1)
+
+
+def test_lock_policy_backoff_strategies():
+    c_instance = C()
+    lock = hold_lock(c_instance)
+
+    for strategy in ["constant", "exponential", "decorrelated_jitter"]:
+        retries = []
+        policy = LockPolicy.bounded(
+            4,
+            backoff=0.01,
+            backoff_strategy=strategy,
+            on_retry=lambda attempt, sleep: retries.append((attempt, sleep)),
+        )
+        with pytest.raises(redis.exceptions.LockError):
+            c_instance.write_state({"value": 1}, lock_policy=policy)
+
+        assert [attempt for attempt, _ in retries] == [1, 2, 3]
+        sleeps = [sleep for _, sleep in retries]
+        if strategy == "constant":
+            assert sleeps == [0.01, 0.01, 0.01]
+        elif strategy == "exponential":
+            assert sleeps == [0.01, 0.02, 0.04]
+        else:
+            assert all(0.01 <= sleep for sleep in sleeps)
+
+    lock.release()
+
+
+class FlakyLock:
+    name = "flaky"
+
+    def __init__(self, failures):
+        self.failures = failures
+
+    def acquire(self, blocking=True, blocking_timeout=None):
+        if self.failures:
+            self.failures -= 1
+            raise redis.exceptions.ConnectionError("Connection refused.")
+        return True
+
+
+def test_lock_policy_connection_errors():
+    # Connection errors use up attempts instead of escaping
+    LockPolicy.bounded(3, backoff=0.01).acquire(FlakyLock(2))
+
+    with pytest.raises(redis.exceptions.LockError):
+        LockPolicy.bounded(2, backoff=0.01).acquire(FlakyLock(2))
+
+    with pytest.raises(redis.exceptions.LockError):
+        LockPolicy.wait(1).acquire(FlakyLock(1))
@@ -0,0 +1,146 @@
+from motion import Component, DuplicateRunError
+
+import cloudpickle
+import pytest
+import redis
+import time
+
+Q = Component("QueuedUpdatesComponent")
+
+
+@Q.init_state
+def setUp():
+    return {"value": 0}
+
+
+@Q.update("add")
+def add(state, props):
+    # Slow enough that later updates wait in the queue
+    time.sleep(0.2)
+    return {"value": state["value"] + props["value"]}
+
+
+def test_remove_queued_update():
+    c = Q()
+    for value in [1, 2, 3]:
+        c.run("add", props={"value": value})
+
+    queued = c.find_updates("add", lambda u: u["props"]["value"] == 3)
+    assert len(queued) == 1
+    assert queued[0]["udf_name"] == "add"
+
+    assert c.remove_update("add", queued[0]["identifier"])
+    assert not c.remove_update("add", queued[0]["identifier"])
+    assert all(u["props"]["value"] != 3 for u in c.peek_updates("add"))
+
+    c.flush_update("add")
+    assert c.read_state("value") == 3
+    assert c.peek_updates("add") == []
+    assert c.peek_updates("missing") == []
+
+
+def test_high_priority_update():
+    c = Q()
+    for value in [1, 2]:
+        c.run("add", props={"value": value})
+    c.run("add", props={"value": 10}, high_priority=True)
+
+    queued = c.peek_updates("add")
+    assert queued[0]["props"]["value"] == 10
+    assert queued[0]["high_priority"]
+    assert not queued[-1]["high_priority"]
+
+    c.flush_update("add")
+    assert c.read_state("value") == 13
+
+
+def test_idempotency_key():
+    c = Q()
+    c.run("add", props={"value": 1}, idempotency_key="request_1")
+    # A retry of the same request doesn't queue the update op again
+    with pytest.raises(DuplicateRunError) as e:
+        c.run("add", props={"value": 1}, idempotency_key="request_1")
+    assert e.value.serve_result is None
+    c.run("add", props={"value": 2}, idempotency_key="request_2")
+
+    c.flush_update("add")
+    assert c.read_state("value") == 3
+
+
+def test_temp_keys_while_updating():
+    c = Q()
+    c.run("add", props={"value": 1}, idempotency_key="request_1")
+    time.sleep(0.1)  # The update task is running the update op
+
+    # The running task's heartbeat expires too, but isn't listed
+    temp_keys = [key for key, _ in c.temp_keys()]
+    assert len(temp_keys) == 1
+    assert temp_keys[0].endswith("/idempotency/add/request_1")
+
+    assert c.drain(timeout=10)
+
+
+def test_idempotency_key_failed_enqueue(monkeypatch):
+    c = Q()
+
+    def unavailable(*args, **kwargs):
+        raise redis.exceptions.ConnectionError("Redis is unavailable")
+
+    with monkeypatch.context() as m:
+        m.setattr(redis.Redis, "eval", unavailable)
+        with pytest.raises(redis.exceptions.ConnectionError):
+            c.run("add", props={"value": 1}, idempotency_key="request_1")
+
+    # Nothing was queued, so the key wasn't claimed and the retry runs
+    assert c.peek_updates("add") == []
+    c.run("add", props={"value": 1}, idempotency_key="request_1")
+    c.flush_update("add")
+    assert c.read_state("value") == 1
+
+
+def test_unfinished_update_requeued():
+    writer = Q(disable_update_task=True)
+
+    # An update that a task popped, but died before finishing
+    queue = writer._executor._get_queue_identifier("add", "add")
+    prefix = queue[: -len("/add/add")]
+    writer._executor._redis_con.rpush(
+        f"{prefix}/inflight/deadtask/add/add",
+        cloudpickle.dumps(
+            {"props": {"value": 5}, "identifier": "unfinished", "expire_at": None}
+        ),
+    )
+
+    # The next update task to start puts it back in the queue and runs it
+    c = Q(writer.instance_id)
+    c.flush_update("add")
+    assert c.read_state("value") == 5
+
+
+def test_requeue_inflight():
+    c = Q(disable_update_task=True)
+    queue = c._executor._get_queue_identifier("add", "add")
+    prefix = queue[: -len("/add/add")]
+    c._executor._redis_con.rpush(
+        f"{prefix}/inflight/deadtask/add/add",
+        cloudpickle.dumps(
+            {"props": {"value": 5}, "identifier": "unfinished", "expire_at": None}
+        ),
+    )
+
+    assert c.requeue_inflight() == 1
+    assert c.requeue_inflight() == 0
+    assert [u["identifier"] for u in c.peek_updates("add")] == ["unfinished"]
+
+    # Nothing runs the requeued update op on this handle
+    assert not c.drain(timeout=0.1)
+
+
+def test_drain():
+    c = Q()
+    for value in [1, 2, 3]:
+        c.run("add", props={"value": value})
+
+    assert c.drain(timeout=10)
+    assert c.peek_updates("add") == []
+    assert Q.peek(c.instance_id, "value") == 6
@@ -0,0 +1,44 @@
+import threading
+
+from motion import Component
+
+C = Component("SubscribedComponent")
+
+
+@C.init_state
+def setUp():
+    return {"model": 0, "model_version": 0, "other": 0}
+
+
+@C.update("retrain")
+def retrain(state, props):
+    return {"model": props["model"], "model_version": state["model_version"] + 1}
+
+
+def test_subscribe():
+    events = []
+    received = threading.Event()
+
+    def on_change(event):
+        events.append(event)
+        if len(events) == 3:
+            received.set()
+
+    with C(writer_id="trainer") as c_instance:
+        c_instance.subscribe("model*", on_change)
+
+        c_instance.write_state({"other": 1})
+        c_instance.run("retrain", props={"model": 1}, flush_update=True)
+        c_instance.pop_state("model")
+
+        assert received.wait(timeout=5)
+
+    assert sorted(event["key"] for event in events[:2]) == ["model", "model_version"]
+    assert all(event["version"] == 3 for event in events[:2])
+    assert events[0]["writer"] == "trainer"
+    assert events[2] == {
+        "key": "model",
+        "version": 4,
+        "writer": "trainer",
+        "deleted": True,
+    }
@@ -0,0 +1,65 @@
+from motion import Component, LockPolicy
+
+import pytest
+import redis
+
+C = Component("ShardedComponent", max_state_keys=3)
+
+
+@C.init_state
+def setUp():
+    return {"value": 0}
+
+
+def test_transfer_keys():
+    source = C("shard_a")
+    dest = C("shard_b")
+    source.write_state({"user_1": 1, "user_2": 2})
+
+    source.transfer_keys("shard_b", ["user_2"])
+    assert source.read_state("user_2") is None
+    assert source.read_state("user_1") == 1
+    assert dest.read_state("user_2") == 2
+
+    # And back again
+    dest.transfer_keys("shard_a", ["user_2"])
+    assert source.read_state("user_2") == 2
+    assert dest.read_state("user_2") is None
+
+
+def test_transfer_keys_errors():
+    source = C("shard_c")
+    dest = C("shard_d")
+    source.write_state({"user_1": 1, "user_2": 2})
+    dest.write_state({"user_3": 3, "user_4": 4})
+
+    with pytest.raises(KeyError):
+        source.transfer_keys("shard_d", ["missing"])
+
+    with pytest.raises(ValueError):
+        source.transfer_keys("shard_c", ["user_1"])
+
+    with pytest.raises(ValueError):
+        source.transfer_keys("does_not_exist", ["user_1"])
+
+    # Destination would exceed max_state_keys, so nothing is moved
+    with pytest.raises(ValueError, match="max_state_keys"):
+        source.transfer_keys("shard_d", ["user_1"])
+
+    assert source.read_state("user_1") == 1
+    assert dest.read_state("user_1") is None
+
+
+def test_transfer_keys_lock_held():
+    source = C("shard_e")
+    dest = C("shard_f")
+    source.write_state({"user_1": 1})
+
+    with dest._executor._lock():
+        with pytest.raises(redis.exceptions.LockError):
+            source.transfer_keys(
+                "shard_f", ["user_1"], lock_policy=LockPolicy.fail_fast()
+            )
+
+    assert source.read_state("user_1") == 1
+    assert dest.read_state("user_1") is None
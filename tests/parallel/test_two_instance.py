@@ -31,3 +31,14 @@ def test_redis_saving():
     assert inst2.run("multiply", props={"value": 3}, flush_update=True) == 6
     assert inst2.read_state("value") == 3
     inst2.shutdown()
+
+
+def test_refresh_version():
+    inst1 = Counter(instance_id="refresh")
+    inst2 = Counter(instance_id="refresh")
+    assert inst2.read_state("value") == 1
+    version = inst2.get_version()
+
+    inst1.write_state({"value": 5})
+    assert inst2.get_version() == version
+    assert inst2.get_version(refresh=True) == version + 1
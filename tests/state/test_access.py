@@ -0,0 +1,62 @@
+from motion import Component
+
+Tracked = Component("TrackedComponent", track_access=True)
+
+
+@Tracked.init_state
+def setUp():
+    return {"hot": 0, "cold": 0}
+
+
+@Tracked.serve("read_hot")
+def read_hot(state, props):
+    return state["hot"]
+
+
+def test_access_report():
+    c_instance = Tracked()
+    for i in range(3):
+        c_instance.run("read_hot", props={"i": i})
+    c_instance.read_state("cold")
+
+    assert c_instance.access_report() == {"hot": 3, "cold": 1}
+
+    # Counts are shared by every handle to the instance
+    other = Tracked(c_instance.instance_id)
+    other.read_state("cold")
+    report = other.access_report()
+    assert report == {"hot": 3, "cold": 2}
+    assert list(report.keys()) == ["hot", "cold"]
+
+
+def test_access_tracking_off_by_default():
+    C = Component("UntrackedComponent")
+
+    @C.init_state
+    def setUp():
+        return {"value": 0}
+
+    c_instance = C()
+    assert c_instance.read_state("value") == 0
+    assert c_instance.access_report() == {}
+
+
+@Tracked.serve("read_typed")
+def read_typed(state, props):
+    return state.get_int("hot")
+
+
+def test_internal_reads_not_counted():
+    c_instance = Tracked()
+    c_instance.snapshot_view(["hot", "cold"])
+    c_instance.sample(2)
+    c_instance.get_matching()
+    c_instance.items_with_meta()
+    c_instance.verify()
+    c_instance.init_keys({"hot": 1})
+    assert c_instance.access_report() == {}
+
+    # Typed getters count like any other read
+    c_instance.run("read_typed", props={})
+    c_instance.read_state("hot")
+    assert c_instance.access_report() == {"hot": 2}
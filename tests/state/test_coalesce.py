@@ -0,0 +1,138 @@
+from motion import Component, SchemaError
+
+import pytest
+import redis
+import time
+
+C = Component("CoalesceComponent", schema={"progress": int})
+
+
+@C.init_state
+def setUp():
+    return {"progress": 0}
+
+
+def test_coalesced_writes():
+    c_instance = C(write_coalesce_ms=10000)
+    version = c_instance.get_version()
+
+    for i in range(100):
+        c_instance.write_state({"progress": i})
+
+    # Buffered writes are visible locally but not yet in redis
+    assert c_instance.read_state("progress") == 99
+    assert C.peek(c_instance.instance_id, "progress") == 0
+
+    c_instance.flush_writes()
+    assert C.peek(c_instance.instance_id, "progress") == 99
+    assert c_instance.get_version() == version + 1
+
+
+def test_coalesce_window_flushes():
+    c_instance = C(write_coalesce_ms=20)
+    c_instance.write_state({"progress": 1})
+    c_instance.write_state({"progress": 2})
+
+    time.sleep(0.2)
+    assert C.peek(c_instance.instance_id, "progress") == 2
+
+
+def test_coalesced_writes_flush_on_shutdown():
+    c_instance = C(write_coalesce_ms=10000)
+    c_instance.write_state({"progress": 5})
+    c_instance.shutdown()
+    assert C.peek(c_instance.instance_id, "progress") == 5
+
+
+def test_coalesced_writes_check_schema():
+    c_instance = C(write_coalesce_ms=10000)
+    with pytest.raises(SchemaError):
+        c_instance.write_state({"progress": "done"})
+
+    with pytest.raises(ValueError):
+        C(write_coalesce_ms=0)
+
+
+def test_coalesced_write_versions():
+    c_instance = C(write_coalesce_ms=10000)
+    version = c_instance.get_version()
+    assert c_instance.write_state({"progress": 1}) is None
+    assert c_instance.flush_writes() == version + 1
+    assert c_instance.flush_writes() is None
+
+
+def test_flush_and_wait():
+    c_instance = C(write_coalesce_ms=10000)
+    version = c_instance.get_version()
+    c_instance.write_state({"progress": 3})
+    assert c_instance.flush_and_wait() == version + 1
+    assert C.peek(c_instance.instance_id, "progress") == 3
+
+    # Nothing buffered, so the current version is confirmed
+    assert c_instance.flush_and_wait() == version + 1
+
+    # The test redis has no replicas
+    with pytest.raises(TimeoutError):
+        c_instance.flush_and_wait(replicas=1, timeout=0.1)
+
+
+def test_flush_and_wait_one_connection(monkeypatch):
+    c_instance = C(write_coalesce_ms=10000)
+    c_instance.write_state({"progress": 4})
+
+    clients = []
+    pipeline = redis.Redis.pipeline
+    wait = redis.Redis.wait
+
+    def record_pipeline(self, *args, **kwargs):
+        clients.append(self)
+        return pipeline(self, *args, **kwargs)
+
+    def record_wait(self, *args, **kwargs):
+        clients.append(self)
+        return wait(self, *args, **kwargs)
+
+    monkeypatch.setattr(redis.Redis, "pipeline", record_pipeline)
+    monkeypatch.setattr(redis.Redis, "wait", record_wait)
+    with pytest.raises(TimeoutError):
+        c_instance.flush_and_wait(replicas=1, timeout=0.1)
+
+    # The flushed writes and the WAIT went over the same connection
+    assert len(clients) >= 2
+    assert all(client is clients[-1] for client in clients)
+    assert clients[-1].connection is not None
+    assert C.peek(c_instance.instance_id, "progress") == 4
+
+
+def test_failed_flush_keeps_writes(monkeypatch):
+    c_instance = C(write_coalesce_ms=10000)
+    c_instance.write_state({"progress": 7})
+
+    def fail(*args, **kwargs):
+        raise ConnectionError("redis went away")
+
+    monkeypatch.setattr(c_instance._executor, "_saveState", fail)
+    with pytest.raises(ConnectionError):
+        c_instance.flush_writes()
+    assert C.peek(c_instance.instance_id, "progress") == 0
+
+    # The writes are still buffered, so the next flush sends them
+    monkeypatch.undo()
+    c_instance.flush_writes()
+    assert C.peek(c_instance.instance_id, "progress") == 7
+
+
+def test_failed_flush_on_shutdown(monkeypatch):
+    c_instance = C(write_coalesce_ms=10000)
+    c_instance.write_state({"progress": 8})
+
+    def fail(*args, **kwargs):
+        raise ConnectionError("redis went away")
+
+    monkeypatch.setattr(c_instance._executor, "_saveState", fail)
+
+    # The flush error is logged and the handle still shuts down
+    c_instance.shutdown()
+    assert not c_instance._executor.scheduler_thread.is_alive()
+    assert not c_instance._executor.monitor_thread.is_alive()
+    assert not hasattr(c_instance._executor, "running")
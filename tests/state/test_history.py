@@ -0,0 +1,106 @@
+from motion import Component, LockPolicy
+
+import pytest
+import redis
+
+C = Component("HistoryComponent", history_size=3)
+
+
+@C.init_state
+def setUp():
+    return {"value": 0}
+
+
+@C.update("add")
+def add(state, props):
+    return {"value": state["value"] + props["value"]}
+
+
+def test_get_at_version():
+    c_instance = C()
+    c_instance.write_state({"other": "a"})
+    c_instance.run("add", props={"value": 5}, flush_update=True)
+    assert c_instance.get_version() == 3
+
+    assert c_instance.get_at_version("value", 1) == 0
+    assert c_instance.get_at_version("value", 2) == 0
+    assert c_instance.get_at_version("value", 3) == 5
+
+    # Key did not exist yet
+    assert c_instance.get_at_version("other", 1, "missing") == "missing"
+    assert c_instance.get_at_version("other", 3) == "a"
+
+    # Deleted keys read as missing after the deletion
+    c_instance.pop_state("other")
+    assert c_instance.get_at_version("other", 3) == "a"
+    assert c_instance.get_at_version("other", 4) is None
+
+    with pytest.raises(ValueError):
+        c_instance.get_at_version("value", 10)
+
+
+def test_history_is_trimmed():
+    c_instance = C()
+    for i in range(1, 5):
+        c_instance.write_state({"value": i})
+
+    assert c_instance.get_at_version("value", 5) == 4
+    assert c_instance.get_at_version("value", 3) == 2
+    with pytest.raises(ValueError, match="older than"):
+        c_instance.get_at_version("value", 1)
+
+
+def test_history_disabled():
+    NoHistory = Component("NoHistoryComponent")
+
+    @NoHistory.init_state
+    def setUp():
+        return {"value": 0}
+
+    c_instance = NoHistory()
+    with pytest.raises(ValueError, match="history_size"):
+        c_instance.get_at_version("value", 1)
+
+
+def test_rollback():
+    c_instance = C()
+    c_instance.write_state({"value": 1, "bad": True})
+    c_instance.pop_state("bad")
+    c_instance.write_state({"value": 2, "worse": True})
+    assert c_instance.get_version() == 4
+
+    assert c_instance.rollback(2) == 5
+    assert c_instance.read_state("value") == 1
+    assert c_instance.read_state("bad") is True
+    assert c_instance.read_state("worse") is None
+
+    # The rollback is itself a version that can be read and rolled back
+    assert c_instance.get_at_version("value", 4) == 2
+    assert c_instance.get_at_version("value", 5) == 1
+    c_instance.rollback(4)
+    assert c_instance.read_state("value") == 2
+    assert c_instance.read_state("bad") is None
+
+    # Other handles see the rolled back state
+    other = C(c_instance.instance_id)
+    assert other.read_state("worse") is True
+
+    with pytest.raises(ValueError):
+        c_instance.rollback(0)
+
+
+def test_rollback_lock_held():
+    c_instance = C()
+    c_instance.write_state({"value": 1})
+    version = c_instance.get_version()
+
+    lock = c_instance._executor._redis_con.lock(
+        c_instance._executor._Executor__lock_prefix, timeout=120
+    )
+    lock.acquire()
+    with pytest.raises(redis.exceptions.LockError):
+        c_instance.rollback(1, lock_policy=LockPolicy.fail_fast())
+    lock.release()
+
+    assert c_instance.read_state("value") == 1
+    assert c_instance.get_version() == version
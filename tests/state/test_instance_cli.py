@@ -2,6 +2,10 @@ from motion import Component, clear_instance, inspect_state, get_instances
 
 import pytest
 import os
+import redis
+
+from motion.cli import connection_kwargs
+from motion.utils import doctor
 
 C = Component("MyComponent")
 
@@ -58,3 +62,84 @@ def test_list_instances():
     instance_ids = get_instances(C.name)
 
     assert len(instance_ids) >= 1
+
+
+def test_doctor(monkeypatch):
+    report = doctor()
+    assert [check["check"] for check in report] == [
+        "connection",
+        "latency",
+        "permissions",
+        "read_write",
+        "publish",
+        "lock",
+        "keyspace_notifications",
+    ]
+    assert all(check["ok"] is not False for check in report)
+
+    # Managed Redis services often disallow CONFIG
+    def disallowed(*args, **kwargs):
+        raise redis.exceptions.ResponseError("unknown command 'CONFIG'")
+
+    monkeypatch.setattr(redis.Redis, "config_get", disallowed)
+    assert doctor()[-1]["ok"] is None
+
+    report = doctor({"host": "localhost", "port": 1, "db": 0})
+    assert len(report) == 1
+    assert not report[0]["ok"]
+
+
+def test_doctor_denied_command(monkeypatch):
+    execute_command = redis.Redis.execute_command
+
+    def deny_lmove(self, *args, **kwargs):
+        if args[:2] == ("ACL", "DRYRUN") and args[3] == "LMOVE":
+            return b"This user has no permissions to run the 'lmove' command"
+        return execute_command(self, *args, **kwargs)
+
+    monkeypatch.setattr(redis.Redis, "execute_command", deny_lmove)
+    permissions = doctor()[2]
+    assert permissions["check"] == "permissions"
+    assert permissions["ok"] is False
+    assert "LMOVE" in permissions["detail"]
+
+
+D = Component("OtherRedisComponent", redis_params={"db": 1})
+
+
+@D.init_state
+def setUp():
+    return {"value": 0}
+
+
+def test_instance_utils_redis_params():
+    d_instance = D()
+    instance_name = d_instance.instance_name
+    d_instance.shutdown()
+
+    # The instance is only found in the Redis the component uses
+    with pytest.raises(ValueError):
+        inspect_state(instance_name)
+    assert inspect_state(instance_name, redis_params={"db": 1}) == {"value": 0}
+    assert d_instance.instance_id in get_instances(D.name, redis_params={"db": 1})
+    assert doctor({"db": 1})[0]["ok"]
+
+    # The CLI loads the component to use its Redis
+    kwargs = connection_kwargs("tests.state.test_instance_cli:D")
+    assert clear_instance(instance_name, **kwargs)
+    assert not get_instances(D.name, **kwargs)
+
+
+def test_redis_params_resolved_lazily(monkeypatch):
+    E = Component("LazyRedisComponent", redis_params={"host": "localhost"})
+
+    @E.init_state
+    def setUp():
+        return {"value": 0}
+
+    # Settings loaded after the component is defined still apply
+    monkeypatch.setenv("MOTION_REDIS_DB", "1")
+    e_instance = E()
+    assert e_instance._executor._redis_params.db == 1
+    assert E.peek(e_instance.instance_id, "value") == 0
+    e_instance.shutdown()
@@ -0,0 +1,133 @@
+from motion import Component
+from motion.utils import DEFAULT_KEY_SCHEME
+
+import pytest
+
+C = Component("RawStateComponent", max_state_bytes=10000)
+
+
+@C.init_state
+def setUp():
+    return {"value": 0}
+
+
+def test_raw_state_round_trip():
+    source = C()
+    source.write_state({"value": 1, "items": [1, 2, 3]})
+    raw_state = source.get_raw_state()
+    assert isinstance(raw_state, bytes)
+
+    dest = C()
+    version = dest.get_version()
+    assert dest.set_raw_state(raw_state) == version + 1
+    assert dest.read_state("items") == [1, 2, 3]
+    assert dest.get_raw_state() == raw_state
+    assert dest.last_modified("items") is not None
+
+    # Other handles pick up the new state
+    assert C(dest.instance_id).read_state("value") == 1
+
+
+def test_set_raw_state_checks():
+    c_instance = C()
+    with pytest.raises(TypeError):
+        c_instance.set_raw_state("not bytes")
+
+    with pytest.raises(ValueError):
+        c_instance.set_raw_state(b"not a pickle")
+
+    # Too big for this component's max_state_bytes
+    other = Component("RawStateComponent")()
+    other.write_state({"items": list(range(10000))})
+    with pytest.raises(ValueError):
+        c_instance.set_raw_state(other.get_raw_state())
+
+    assert c_instance.read_state("value") == 0
+
+
+def test_digest():
+    c_instance = C()
+    digest = c_instance.digest()
+    assert digest is not None
+    assert c_instance.digest() == digest
+
+    C(c_instance.instance_id).write_state({"value": 1})
+    new_digest = c_instance.digest()
+    assert new_digest != digest
+
+    # Same state, same digest
+    other = C()
+    other.set_raw_state(c_instance.get_raw_state())
+    assert other.digest() == new_digest
+
+
+def test_on_decode_error():
+    c_instance = C()
+    c_instance._executor._redis_con.set(
+        f"{DEFAULT_KEY_SCHEME.state}:{c_instance.instance_name}", b"not a pickle"
+    )
+
+    with pytest.raises(Exception):
+        C(c_instance.instance_id).read_state("value")
+
+    Skip = Component("RawStateComponent", on_decode_error="skip")
+    skipped = Skip(c_instance.instance_id)
+    assert skipped.read_state("value") is None
+
+    # Writing the skipped state back would destroy the stored bytes
+    with pytest.raises(ValueError):
+        skipped.write_state({"value": 1})
+    assert c_instance.get_raw_state() == b"not a pickle"
+
+    Ignore = Component(
+        "RawStateComponent", on_decode_error=lambda instance_id, raw, e: None
+    )
+    with pytest.raises(ValueError):
+        Ignore(c_instance.instance_id).write_state({"value": 1})
+
+    Invalid = Component(
+        "RawStateComponent", on_decode_error=lambda instance_id, raw, e: raw
+    )
+    with pytest.raises(TypeError):
+        Invalid(c_instance.instance_id).read_state("value")
+
+    Recover = Component(
+        "RawStateComponent",
+        on_decode_error=lambda instance_id, raw, e: {"value": -1, "raw": raw},
+    )
+    recovered = Recover(c_instance.instance_id)
+    assert recovered.read_state("value") == -1
+    assert recovered.read_state("raw") == b"not a pickle"
+
+    # Repairing the stored state makes it writable again
+    skipped.set_raw_state(C().get_raw_state())
+    skipped.write_state({"value": 2})
+    assert C(c_instance.instance_id).read_state("value") == 2
+
+    with pytest.raises(ValueError):
+        Component("RawStateComponent", on_decode_error="ignore")
+
+
+def test_verify():
+    c_instance = C()
+    c_instance.write_state({"items": [1]})
+    version = c_instance.get_version()
+    assert c_instance.verify() == {
+        "cached_version": version,
+        "stored_version": version,
+        "mismatched_keys": [],
+    }
+
+    # Mutating a live return changes the cache but not redis
+    c_instance.read_state("items").append(2)
+    assert c_instance.verify()["mismatched_keys"] == ["items"]
+
+    c_instance.verify(heal=True)
+    assert c_instance.read_state("items") == [1]
+    assert c_instance.verify()["mismatched_keys"] == []
+
+    # Writes from other handles only make the cache stale
+    C(c_instance.instance_id).write_state({"items": [3]})
+    report = c_instance.verify()
+    assert report["stored_version"] == version + 1
+    assert report["cached_version"] == version
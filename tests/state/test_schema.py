@@ -0,0 +1,200 @@
+from motion import Component, SchemaError
+
+import cloudpickle
+import pytest
+
+Typed = Component(
+    "TypedComponent", schema={"count": int, "name": str, "config": dict}
+)
+
+
+@Typed.init_state
+def setUp():
+    return {"count": 0, "name": "typed", "config": {"threshold": 0.5}}
+
+
+@Typed.serve("count")
+def get_count(state, props):
+    return state.get_int("count")
+
+
+@Typed.serve("threshold")
+def get_threshold(state, props):
+    return state.get_dict("config")["threshold"]
+
+
+@Typed.serve("name_as_float")
+def get_name_as_float(state, props):
+    return state.get_float("name")
+
+
+def test_invalid_schema():
+    with pytest.raises(TypeError):
+        Component("BadSchema", schema={"count": "int"})
+
+    with pytest.raises(TypeError):
+        Component("BadSchema", schema=[int])
+
+
+def test_typed_accessors():
+    c_instance = Typed()
+    assert c_instance.run("count", ignore_cache=True) == 0
+    assert c_instance.run("threshold", ignore_cache=True) == 0.5
+
+    with pytest.raises(SchemaError, match="name"):
+        c_instance.run("name_as_float", ignore_cache=True)
+
+
+def test_schema_not_stored():
+    c_instance = Typed()
+    c_instance.write_state({"count": 1})
+
+    # The stored state doesn't carry the schema, but loaded states do
+    stored = cloudpickle.loads(c_instance.get_raw_state())
+    assert stored._schema is None
+    other = Typed(c_instance.instance_id)
+    assert other.read_state("count") == 1
+    assert other._executor._state._schema["count"] is int
+
+
+def test_schema_enforced_on_write():
+    c_instance = Typed()
+    version = c_instance.get_version()
+
+    with pytest.raises(SchemaError) as e:
+        c_instance.write_state({"count": "1"})
+    assert e.value.key == "count"
+
+    # Typos in key names fail fast
+    with pytest.raises(SchemaError, match="not declared"):
+        c_instance.write_state({"cuont": 1})
+
+    # bools are not ints
+    with pytest.raises(SchemaError):
+        c_instance.write_state({"count": True})
+
+    # None of the rejected writes are visible
+    assert c_instance.read_state("count") == 0
+    assert c_instance.read_state("cuont") is None
+    assert c_instance.get_version() == version
+
+    c_instance.write_state({"count": 1})
+    assert c_instance.run("count", ignore_cache=True) == 1
+
+
+def test_schema_validated_on_read():
+    c_instance = Typed()
+
+    # Simulate a value that was stored before the schema was declared
+    c_instance._executor._state._schema = None
+    c_instance._executor._state["count"] = "1"
+
+    with pytest.raises(SchemaError) as e:
+        c_instance._executor._state.get_int("count")
+    assert e.value.key == "count"
+
+
+BadInit = Component("BadInitComponent", schema={"count": int})
+
+
+@BadInit.init_state
+def bad_setup():
+    return {"count": 0, "extra": 1}
+
+
+def test_schema_enforced_on_init():
+    with pytest.raises(SchemaError):
+        BadInit()
+
+
+mismatches = []
+
+
+def versioned_component(schema_version):
+    # Simulates deploying the same component with a different schema version
+    versioned = Component(
+        "VersionedComponent", schema={"count": int}, schema_version=schema_version
+    )
+
+    @versioned.init_state
+    def setup():
+        return {"count": 0}
+
+    @versioned.schema_mismatch
+    def on_mismatch(instance_id, stored_version, schema_version):
+        mismatches.append((instance_id, stored_version, schema_version))
+
+    return versioned
+
+
+def test_schema_version():
+    with pytest.raises(ValueError):
+        Component("BadSchemaVersion", schema_version=-1)
+
+    # New instances store the declared version
+    c_instance = versioned_component(1)("versioned")
+    assert mismatches == []
+    c_instance.shutdown()
+
+    c_instance = versioned_component(2)("versioned")
+    assert mismatches == [("versioned", 1, 2)]
+    c_instance.shutdown()
+
+    # The stored version is updated, so the hook doesn't fire again
+    c_instance = versioned_component(2)("versioned")
+    assert mismatches == [("versioned", 1, 2)]
+    c_instance.shutdown()
+
+
+def upgraded_component(schema, schema_version, upgrade=None):
+    upgraded = Component(
+        "UpgradedComponent", schema=schema, schema_version=schema_version
+    )
+
+    @upgraded.init_state
+    def setup():
+        return {"count": 0}
+
+    if upgrade is not None:
+        upgraded.upgrade_state(upgrade)
+
+    return upgraded
+
+
+def test_upgrade_state():
+    upgrades = []
+
+    def upgrade(state, stored_version):
+        upgrades.append(stored_version)
+        return {"counts": {"total": state["count"]}}
+
+    c_instance = upgraded_component({"count": int}, 1)("upgraded")
+    c_instance.write_state({"count": 5})
+    c_instance.shutdown()
+
+    c_instance = upgraded_component({"counts": dict}, 2, upgrade)("upgraded")
+    assert upgrades == [1]
+    assert c_instance.read_state("counts") == {"total": 5}
+    assert c_instance.read_state("count") is None
+    c_instance.shutdown()
+
+    # Already upgraded, so the hook doesn't run again
+    c_instance = upgraded_component({"counts": dict}, 2, upgrade)("upgraded")
+    assert upgrades == [1]
+    c_instance.shutdown()
+
+
+def test_failed_upgrade_leaves_state():
+    c_instance = upgraded_component({"count": int}, 1)("failed_upgrade")
+    c_instance.shutdown()
+
+    def bad_upgrade(state, stored_version):
+        # Doesn't match the new schema
+        return {"count": "zero"}
+
+    with pytest.raises(SchemaError):
+        upgraded_component({"count": int}, 2, bad_upgrade)("failed_upgrade")
+
+    c_instance = upgraded_component({"count": int}, 1)("failed_upgrade")
+    assert c_instance.read_state("count") == 0
+    c_instance.shutdown()
@@ -0,0 +1,55 @@
+from motion import Component
+
+import pytest
+
+Limited = Component("LimitedComponent", max_state_bytes=1000, max_state_keys=2)
+
+
+@Limited.init_state
+def setUp():
+    return {"value": 0}
+
+
+@Limited.update("grow")
+def grow(state, props):
+    return {"blob": b"0" * props["size"]}
+
+
+def test_invalid_limits():
+    with pytest.raises(ValueError):
+        Component("BadLimits", max_state_bytes=0)
+
+    with pytest.raises(ValueError):
+        Component("BadLimits", max_state_keys=-1)
+
+
+def test_max_state_keys():
+    c_instance = Limited()
+    c_instance.write_state({"other": 1})
+    version = c_instance.get_version()
+
+    with pytest.raises(ValueError, match="max_state_keys"):
+        c_instance.write_state({"one_too_many": 2})
+
+    # The rejected write should not be visible
+    assert c_instance.read_state("one_too_many") is None
+    assert c_instance.read_state("other") == 1
+    assert c_instance.get_version() == version
+
+
+def test_max_state_bytes():
+    c_instance = Limited()
+
+    with pytest.raises(ValueError, match="max_state_bytes"):
+        c_instance.write_state({"blob": b"0" * 2000})
+
+    assert c_instance.read_state("blob") is None
+
+    # Update ops are subject to the same limit
+    c_instance.run("grow", props={"size": 10}, flush_update=True)
+    assert c_instance.read_state("blob") == b"0" * 10
+
+    with pytest.raises(RuntimeError):
+        c_instance.run("grow", props={"size": 2000}, flush_update=True)
+
+    assert c_instance.read_state("blob") == b"0" * 10
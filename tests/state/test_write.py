@@ -1,6 +1,11 @@
-from motion import Component
+import os
+import time
+
+from motion import Component, JsonPatchError
+from motion.utils import DEFAULT_KEY_SCHEME, DEFAULT_KEY_TTL, loadKeyMeta, saveState
 
 import pytest
+import redis
 
 C = Component("MyComponent")
 
@@ -36,3 +41,410 @@ def test_write_state():
 def test_read_instance_id():
     c_instance = C("some_id")
     assert c_instance.run("my_key", ignore_cache=True) == "some_id"
+
+
+def test_pop_state():
+    c_instance = C()
+    c_instance.write_state({"value": 1, "to_pop": [1, 2, 3]})
+
+    assert c_instance.pop_state("to_pop") == [1, 2, 3]
+    assert c_instance.read_state("to_pop") is None
+
+    # Popping a missing key returns the default and doesn't bump the version
+    version = c_instance.get_version()
+    assert c_instance.pop_state("to_pop", "default") == "default"
+    assert c_instance.get_version() == version
+    assert c_instance.read_state("value") == 1
+
+
+def test_last_modified():
+    c_instance = C()
+    created_at = c_instance.last_modified("value")
+    assert created_at is not None
+    assert c_instance.last_modified("missing") is None
+
+    c_instance.write_state({"value2": 2})
+    assert c_instance.last_modified("value2") >= created_at
+    assert c_instance.last_modified("value") == created_at
+
+    items = c_instance.items_with_meta()
+    assert items["value"]["value"] == 0
+    assert items["value"]["modified_at"] == created_at
+    assert items["value"]["version"] == 1
+    assert items["value2"]["version"] == c_instance.get_version()
+
+    # Popped keys lose their metadata
+    c_instance.pop_state("value2")
+    assert c_instance.last_modified("value2") is None
+    assert "value2" not in c_instance.items_with_meta()
+
+
+def test_snapshot_view():
+    c_instance = C()
+    c_instance.write_state({"value": 1, "value2": 2})
+    view = c_instance.snapshot_view(["value", "value2", "missing"])
+    assert dict(view) == {"value": 1, "value2": 2}
+
+    with pytest.raises(TypeError):
+        view["value"] = 3
+
+    # Later writes don't change the view
+    c_instance.write_state({"value": 3})
+    assert view["value"] == 1
+
+
+def test_schedule_write():
+    c_instance = C()
+    c_instance.schedule_write({"value": 5}, at=time.time() + 0.5)
+    assert c_instance.read_state("value") == 0
+
+    time.sleep(2)
+    assert c_instance.read_state("value") == 5
+
+    with pytest.raises(TypeError):
+        c_instance.schedule_write(5, at=time.time())
+
+
+def test_failed_schedule_write_retried(monkeypatch):
+    c_instance = C()
+
+    def fail(*args, **kwargs):
+        raise ConnectionError("redis went away")
+
+    monkeypatch.setattr(c_instance._executor, "_updateState", fail)
+    c_instance.schedule_write({"value": 6}, at=time.time())
+    time.sleep(1.5)
+    assert C.peek(c_instance.instance_id, "value") == 0
+
+    # The failed write stayed scheduled, so the next poll applies it
+    monkeypatch.undo()
+    time.sleep(1.5)
+    assert C.peek(c_instance.instance_id, "value") == 6
+
+
+def test_scheduled_write_removed_after_applying(monkeypatch):
+    c_instance = C()
+    scheduled_key = c_instance._executor._Executor__scheduled_key
+    redis_con = c_instance._executor._redis_con
+
+    # The process dies after the write but before removing the entry
+    def fail(*args, **kwargs):
+        raise ConnectionError("redis went away")
+
+    monkeypatch.setattr(redis.Redis, "eval", fail)
+    c_instance.schedule_write({"value": 7}, at=time.time())
+    time.sleep(1.5)
+    assert C.peek(c_instance.instance_id, "value") == 7
+    assert redis_con.zcard(scheduled_key) == 1
+
+    # The next poll applies it again and removes it
+    monkeypatch.undo()
+    time.sleep(1.5)
+    assert redis_con.zcard(scheduled_key) == 0
+
+
+def test_shutdown_stops_scheduler():
+    c_instance = C()
+    c_instance.shutdown()
+    assert not c_instance._executor.scheduler_thread.is_alive()
+
+    # Also when the update task already stopped
+    c_instance = C()
+    c_instance._executor.running.value = False
+    c_instance.shutdown()
+    assert not c_instance._executor.scheduler_thread.is_alive()
+    assert not c_instance._executor.monitor_thread.is_alive()
+
+
+def test_sample():
+    c_instance = C()
+    c_instance.write_state({f"key_{i}": i for i in range(20)})
+
+    sampled = c_instance.sample(5, seed=1)
+    assert len(sampled) == 5
+    assert all(c_instance.read_state(key) == value for key, value in sampled.items())
+    assert c_instance.sample(5, seed=1) == sampled
+
+    assert len(c_instance.sample(100)) == 21
+    with pytest.raises(ValueError):
+        c_instance.sample(-1)
+
+
+def test_items_since():
+    c_instance = C()
+    changed, version = c_instance.items_since(0)
+    assert changed == {"value": 0}
+
+    c_instance.write_state({"value2": 1})
+    changed, new_version = c_instance.items_since(version)
+    assert changed == {"value2": 1}
+    assert new_version == version + 1
+
+    changed, _ = c_instance.items_since(new_version)
+    assert changed == {}
+
+
+def test_last_writer():
+    c_instance = C(writer_id="writer_a")
+    assert c_instance.last_writer("value") == "writer_a"
+
+    # Another instance handle writing to the same state
+    other = C(c_instance.instance_id, writer_id="writer_b")
+    other.write_state({"value": 5})
+    assert c_instance.last_writer("value") == "writer_b"
+    assert c_instance.items_with_meta()["value"]["writer"] == "writer_b"
+
+    # Defaults to the host and process id
+    default = C()
+    assert default.last_writer("value").endswith(f":{os.getpid()}")
+
+
+def test_cache_returns_copy():
+    Copied = Component("CopiedComponent", cache_returns="copy")
+
+    @Copied.init_state
+    def setUp():
+        return {"config": {"threshold": 0.5}}
+
+    c_instance = Copied()
+    config = c_instance.read_state("config")
+    config["threshold"] = 0.9
+    assert c_instance.read_state("config") == {"threshold": 0.5}
+
+    c_instance.items_with_meta()["config"]["value"]["threshold"] = 0.9
+    assert c_instance.read_state("config") == {"threshold": 0.5}
+
+    # Live returns share the in-memory state
+    live_instance = C()
+    live_instance.write_state({"config": {"threshold": 0.5}})
+    live_instance.read_state("config")["threshold"] = 0.9
+    assert live_instance.read_state("config") == {"threshold": 0.9}
+
+    with pytest.raises(ValueError):
+        Component("BadCacheReturns", cache_returns="clone")
+
+
+def test_last_op_info():
+    c_instance = C()
+    assert c_instance.last_op_info()["op"] == "save"
+
+    c_instance.write_state({"value2": [1, 2, 3]})
+    info = c_instance.last_op_info()
+    assert info["op"] == "save"
+    assert info["serializer"] == "cloudpickle"
+    assert info["bytes"] > 0
+    assert info["keys"] == ["value2"]
+
+    metrics = c_instance.metrics()
+    assert metrics["save_count"] == 2
+    assert metrics["save_bytes"] >= info["bytes"]
+
+    # Another handle loads the state
+    other = C(c_instance.instance_id)
+    other.read_state("value2")
+    assert other.last_op_info()["op"] == "load"
+    assert other.metrics()["load_count"] == 1
+
+
+def test_write_returns_version():
+    c_instance = C()
+    version = c_instance.write_state({"value": 1})
+    assert version == c_instance.get_version()
+
+    # Another handle writes in between
+    C(c_instance.instance_id).write_state({"value": 2})
+    assert c_instance.write_state({"value": 3}) == version + 2
+
+    # Empty writes don't bump the version
+    assert c_instance.write_state({}) == version + 2
+
+
+def test_save_dropped_if_version_changes():
+    c_instance = C()
+    version = c_instance.write_state({"value": 1})
+    redis_con = c_instance._executor._redis_con
+    version_key = f"{DEFAULT_KEY_SCHEME.version}:{c_instance.instance_name}"
+
+    # Another writer saves after the version check, before EXEC
+    def save_state_func(state):
+        redis_con.incr(version_key)
+        return state
+
+    state = c_instance._executor._state
+    state["value"] = 2
+    new_version = saveState(
+        state,
+        version,
+        redis_con,
+        c_instance.instance_name,
+        save_state_func,
+        updated_keys=["value"],
+    )
+    assert new_version == -1
+    assert C(c_instance.instance_id).read_state("value") == 1
+    assert int(redis_con.get(version_key)) == version + 1
+    meta = loadKeyMeta(redis_con, c_instance.instance_name, ["value"])
+    assert meta["value"]["version"] == version
+
+
+def test_get_matching():
+    c_instance = C()
+    c_instance.write_state(
+        {"stats/count": 10, "stats/mean": 0.5, "stats/name": "a", "other": 1}
+    )
+
+    assert c_instance.get_matching("stats/", (int, float)) == {
+        "stats/count": 10,
+        "stats/mean": 0.5,
+    }
+    assert c_instance.get_matching("stats/", str) == {"stats/name": "a"}
+    assert set(c_instance.get_matching("stats/")) == {
+        "stats/count",
+        "stats/mean",
+        "stats/name",
+    }
+    assert c_instance.get_matching(types=int) == {
+        "value": 0,
+        "stats/count": 10,
+        "other": 1,
+    }
+
+
+def test_rename_keys():
+    c_instance = C()
+    c_instance.write_state({"modelA": "a", "modelB": "b"})
+    version = c_instance.get_version()
+
+    assert c_instance.rename_keys({"modelA": "models/a", "modelB": "models/b"}) == (
+        version + 1
+    )
+    assert c_instance.read_state("models/a") == "a"
+    assert c_instance.read_state("modelA") is None
+    assert C(c_instance.instance_id).read_state("models/b") == "b"
+
+    # Swapping keys is allowed
+    version = c_instance.rename_keys(
+        {"models/a": "models/b", "models/b": "models/a"}
+    )
+    assert c_instance.read_state("models/a") == "b"
+
+    with pytest.raises(KeyError):
+        c_instance.rename_keys({"missing": "other"})
+
+    with pytest.raises(ValueError):
+        c_instance.rename_keys({"models/a": "value"})
+
+    assert c_instance.read_state("value") == 0
+    assert c_instance.get_version() == version
+
+
+def test_apply():
+    c_instance = C()
+    c_instance.write_state({"a": 1, "count": 0})
+
+    results = c_instance.apply(
+        [("delete", "a"), ("set", "b", 2), ("incr", "count"), ("incr", "count", 5)]
+    )
+    assert results == [1, None, 1, 6]
+    version = c_instance.get_version()
+
+    other = C(c_instance.instance_id)
+    assert other.read_state("a") is None
+    assert other.read_state("b") == 2
+    assert other.read_state("count") == 6
+
+    # A failing op leaves the state untouched
+    with pytest.raises(TypeError):
+        c_instance.apply([("set", "c", 3), ("incr", "b", "x")])
+    with pytest.raises(ValueError):
+        c_instance.apply([("replace", "b", 3)])
+    for bad_op in [("set", "c"), ("delete", "b", 1), ("incr", "count", 1, 2), ()]:
+        with pytest.raises(ValueError):
+            c_instance.apply([("set", "c", 3), bad_op])
+    assert c_instance.read_state("c") is None
+    assert c_instance.get_version() == version
+
+
+def test_init_keys():
+    c_instance = C()
+    assert c_instance.init_keys({"value": 5, "limit": 10}) == {
+        "value": 0,
+        "limit": 10,
+    }
+    version = c_instance.get_version()
+
+    # Nothing is missing the second time, so nothing is written
+    other = C(c_instance.instance_id)
+    assert other.init_keys({"limit": 20}) == {"limit": 10}
+    assert other.get_version() == version
+
+
+def test_json_patch():
+    c_instance = C()
+    c_instance.write_state({"config": {"threshold": 0.5, "tags": ["a"]}})
+    version = c_instance.get_version()
+
+    value, new_version = c_instance.patch(
+        "config",
+        [
+            {"op": "replace", "path": "/threshold", "value": 0.8},
+            {"op": "add", "path": "/tags/-", "value": "b"},
+            {"op": "copy", "from": "/tags/0", "path": "/first_tag"},
+            {"op": "move", "from": "/first_tag", "path": "/tag"},
+            {"op": "test", "path": "/tag", "value": "a"},
+        ],
+    )
+    assert value == {"threshold": 0.8, "tags": ["a", "b"], "tag": "a"}
+    assert new_version == version + 1
+    assert C(c_instance.instance_id).read_state("config") == value
+
+    # A failing op leaves the value untouched
+    with pytest.raises(JsonPatchError):
+        c_instance.patch(
+            "config",
+            [
+                {"op": "remove", "path": "/tag"},
+                {"op": "test", "path": "/threshold", "value": 0.5},
+            ],
+        )
+    with pytest.raises(JsonPatchError):
+        c_instance.patch("config", [{"op": "remove", "path": "/tags/5"}])
+    with pytest.raises(KeyError):
+        c_instance.patch("missing", [])
+    assert c_instance.read_state("config") == value
+    assert c_instance.get_version() == new_version
+
+
+def test_per_call_timeout():
+    c_instance = C()
+    assert c_instance.read_state("value", timeout=0.05) == 0
+    c_instance.write_state({"value": 1}, timeout=0.05)
+    assert C.peek(c_instance.instance_id, "value") == 1
+
+    redis_con = c_instance._executor._timeout_cons[0.05]
+    assert redis_con.connection_pool.connection_kwargs["socket_timeout"] == 0.05
+
+    # The timeout also bounds the wait for the lock
+    other = C(c_instance.instance_id)
+    with other._executor._lock():
+        start = time.time()
+        with pytest.raises(redis.exceptions.LockError):
+            c_instance.write_state({"value": 2}, timeout=0.2)
+        assert time.time() - start < 1
+
+    with pytest.raises(ValueError):
+        c_instance.read_state("value", timeout=0)
+
+
+def test_temp_keys():
+    c_instance = C()
+    assert c_instance.temp_keys() == []
+
+    c_instance.run("my_key", props={"value": 1})
+    time.sleep(0.1)  # Results are cached in the background
+
+    # The cached result expires, but the queues and state don't
+    temp_keys = c_instance.temp_keys()
+    assert len(temp_keys) == 1
+    assert "my_key" in temp_keys[0][0]
+    assert 0 < temp_keys[0][1] <= DEFAULT_KEY_TTL
@@ -0,0 +1,97 @@
+from motion import Component, LockPolicy, MultiInstanceAccessor
+
+import pytest
+import redis
+
+C = Component("NightlyComponent")
+
+
+@C.init_state
+def setUp():
+    return {"value": 0}
+
+
+def test_multi_instance_accessor():
+    instances = [C(f"instance_{i}") for i in range(7)]
+    instances[0].write_state({"value": 10})
+
+    accessor = MultiInstanceAccessor(C, chunk_size=3, num_workers=2)
+    values = accessor.get("value")
+    assert values == {f"instance_{i}": 10 if i == 0 else 0 for i in range(7)}
+
+    assert accessor.set("flag", True) == 7
+    assert all(instance.read_state("flag") for instance in instances)
+
+    # Only instances with the key count as deleted
+    instances[1].pop_state("flag")
+    assert accessor.delete("flag") == 6
+    assert accessor.get("flag", "missing") == {
+        f"instance_{i}": "missing" for i in range(7)
+    }
+
+
+def test_multi_instance_accessor_subset():
+    C("subset_a")
+    C("subset_b")
+
+    accessor = MultiInstanceAccessor(C, ["subset_a", "does_not_exist"])
+    assert accessor.set("value", 5) == 1
+    assert accessor.get("value") == {"subset_a": 5}
+    assert C.peek("subset_b", "value") == 0
+
+    with pytest.raises(ValueError):
+        MultiInstanceAccessor(C, chunk_size=0)
+
+
+def test_multi_instance_accessor_lock_held():
+    C("locked_a")
+    held = C("locked_b")
+
+    accessor = MultiInstanceAccessor(C, ["locked_a", "locked_b"])
+    with held._executor._lock():
+        with pytest.raises(redis.exceptions.LockError):
+            accessor.set("value", 5, lock_policy=LockPolicy.fail_fast())
+
+    # The chunk is written all or nothing
+    assert C.peek("locked_a", "value") == 0
+    assert accessor.set("value", 5) == 2
+
+
+def test_multi_instance_accessor_dev_falls_back_to_prod(monkeypatch):
+    C("prod_only").write_state({"value": 3})
+
+    monkeypatch.setenv("MOTION_ENV", "dev")
+    accessor = MultiInstanceAccessor(C, ["prod_only"])
+    assert accessor.get("value") == {"prod_only": 3}
+    assert "prod_only" in [
+        name.split("__")[1] for name in MultiInstanceAccessor(C).instance_names
+    ]
+
+
+def test_multi_instance_accessor_write_chunks(monkeypatch):
+    instance_ids = [f"chunked_{i}" for i in range(5)]
+    instances = [C(instance_id) for instance_id in instance_ids]
+
+    accessor = MultiInstanceAccessor(
+        C, instance_ids, chunk_size=3, write_chunk_size=2, writer_id="nightly"
+    )
+    chunk_sizes = []
+    load_chunk = accessor._load_chunk
+
+    def record_chunk(redis_con, chunk):
+        chunk_sizes.append(len(chunk))
+        return load_chunk(redis_con, chunk)
+
+    monkeypatch.setattr(accessor, "_load_chunk", record_chunk)
+
+    # Writes hold fewer locks at once than reads fetch states
+    assert accessor.set("value", 1) == 5
+    assert sorted(chunk_sizes) == [1, 2, 2]
+    chunk_sizes.clear()
+    accessor.get("value")
+    assert sorted(chunk_sizes) == [2, 3]
+
+    assert all(instance.last_writer("value") == "nightly" for instance in instances)
+
+    with pytest.raises(ValueError):
+        MultiInstanceAccessor(C, write_chunk_size=0)